@@ -2,6 +2,7 @@
 #![no_main]
 
 use blues_notecard_next::Notecard;
+use blues_notecard_next::transport::Serial;
 
 use defmt::*;
 use {defmt_rtt as _, panic_probe as _};
@@ -59,8 +60,12 @@ async fn main(_spawner: Spawner) {
 
     let delay = Delay;
 
-    // Configure notecard
-    let mut note = Notecard::new(usart, delay);
+    // Configure notecard, pacing is turned off when the firmware keeps up on the AUX UART
+    let config = blues_notecard_next::Config {
+        detect_firmware: true,
+        ..Default::default()
+    };
+    let mut note = Notecard::new_with_config(Serial::new_aux(usart), delay, config);
 
     let hub_get = blues_notecard_next::hub::req::HubGet::default();
     let r = note.transaction(hub_get).await;
@@ -29,3 +29,16 @@ fn note_transaction_derive_macro2(item: proc_macro2::TokenStream) -> deluxe::Res
 pub fn note_transaction_derive_macro(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
     note_transaction_derive_macro2(item.into()).unwrap().into()
 }
+
+#[proc_macro_derive(NoteCommand)]
+pub fn note_command_derive_macro(item: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast: DeriveInput = syn::parse_macro_input!(item);
+
+    let ident = &ast.ident;
+    let (impl_generics, type_generics, where_clause) = ast.generics.split_for_impl();
+
+    quote::quote! {
+        impl #impl_generics NoteCommand for #ident #type_generics #where_clause {}
+    }
+    .into()
+}
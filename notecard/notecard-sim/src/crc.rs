@@ -0,0 +1,23 @@
+//! The `"crc"` field of `note-c`, `"crc":"SSSS:CCCCCCCC"` with the sequence number and the CRC-32
+//! of the JSON without the field.
+
+/// CRC-32 (IEEE 802.3) of `data`.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Append the crc field for `seqno` to the JSON object `response`.
+pub(crate) fn add_crc(response: &str, seqno: &str) -> String {
+    let crc = crc32(response.as_bytes());
+    let body = response.strip_suffix('}').unwrap_or(response);
+    let separator = if body.ends_with('{') { "" } else { "," };
+    format!("{body}{separator}\"crc\":\"{seqno}:{crc:08X}\"}}")
+}
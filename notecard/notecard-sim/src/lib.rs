@@ -0,0 +1,422 @@
+//! # notecard-sim
+//!
+//! A simulated Blues Notecard for integration tests on the host.
+//!
+//! The [`Simulator`] keeps Notecard state: notefiles and notes (`note.add`, `note.get`,
+//! `note.update`, `note.delete`, `note.changes`), hub settings (`hub.set`, `hub.get`),
+//! environment variables (`env.set`, `env.default`, `env.get`) and the clock (`card.time`). It
+//! answers `card.version` as well.
+//!
+//! [`SimSerial`] speaks the newline terminated JSON protocol of the Notecard UART on top of it as
+//! an `embedded_io` and `embedded_io_async` byte stream, so the driver can talk to the simulator
+//! like it would to a Notecard:
+//!
+//! ```rust, ignore
+//! let sim = Simulator::new();
+//! let mut note = Notecard::new(Serial::new(sim.serial()), TokioDelay);
+//! note.transaction(hub_set).await?;
+//! assert_eq!(sim.hub_setting("product"), Some("com.example:test".into()));
+//! ```
+//!
+//! Requests sent with `"cmd"` instead of `"req"` are executed without a response, an `"id"` on a
+//! request is echoed on the response and a `"crc"` field on a request is answered with one
+//! computed over the response.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use embedded_io::{ErrorType, ReadReady};
+use serde_json::{json, Map, Value};
+
+mod crc;
+mod notefile;
+pub use notefile::Note;
+use notefile::Notefile;
+
+/// Device UID reported by the simulator.
+const DEVICE: &str = "dev:000000000000000";
+
+/// Firmware reported by `card.version`.
+const FIRMWARE_MAJOR: u32 = 8;
+const FIRMWARE_MINOR: u32 = 1;
+const FIRMWARE_PATCH: u32 = 3;
+const FIRMWARE_BUILD: u32 = 17004;
+
+/// Notefile used by `note.add` without a `file`.
+const DEFAULT_OUTBOUND_FILE: &str = "data.qo";
+
+/// Notefile used by `note.get` without a `file`.
+const DEFAULT_INBOUND_FILE: &str = "data.qi";
+
+#[derive(Default)]
+struct State {
+    notefiles: BTreeMap<String, Notefile>,
+    hub: Map<String, Value>,
+    env: BTreeMap<String, String>,
+    env_defaults: BTreeMap<String, String>,
+    time: Option<i64>,
+}
+
+/// Simulated Notecard state, clones share the same Notecard.
+#[derive(Clone, Default)]
+pub struct Simulator {
+    state: Arc<Mutex<State>>,
+}
+
+impl Simulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A new byte stream connected to this Notecard.
+    pub fn serial(&self) -> SimSerial {
+        SimSerial {
+            simulator: self.clone(),
+            input: Vec::new(),
+            output: VecDeque::new(),
+        }
+    }
+
+    /// Execute a JSON request line, returns the response line without `\r\n`.
+    ///
+    /// Returns `None` for commands, which have no response.
+    pub fn request(&self, line: &str) -> Option<String> {
+        let mut request = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(request)) => request,
+            Ok(_) | Err(_) => return Some(error_response("invalid request: not a JSON object {io}")),
+        };
+        let seqno = match request.remove("crc") {
+            Some(Value::String(crc)) => crc.split(':').next().map(String::from),
+            _ => None,
+        };
+        let id = request.remove("id");
+
+        let (name, respond) = match (request.remove("req"), request.remove("cmd")) {
+            (Some(Value::String(name)), None) => (name, true),
+            (None, Some(Value::String(name))) => (name, false),
+            _ => return Some(error_response("no request type specified")),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let mut response = match state.handle(&name, &request) {
+            Ok(response) => response,
+            Err(err) => {
+                let mut response = Map::new();
+                response.insert("err".into(), err.into());
+                response
+            }
+        };
+        if let Some(id) = id {
+            response.insert("id".into(), id);
+        }
+
+        let response = Value::Object(response).to_string();
+        respond.then(|| match seqno {
+            Some(seqno) => crc::add_crc(&response, &seqno),
+            None => response,
+        })
+    }
+
+    /// Fix the Notecard clock at `epoch` seconds, it follows the host clock by default.
+    pub fn set_time(&self, epoch: i64) {
+        self.state.lock().unwrap().time = Some(epoch);
+    }
+
+    /// Set an environment variable as Notehub would.
+    pub fn set_env(&self, name: &str, value: &str) {
+        self.state.lock().unwrap().env.insert(name.into(), value.into());
+    }
+
+    /// Value of an environment variable, falling back to its default.
+    pub fn env(&self, name: &str) -> Option<String> {
+        self.state.lock().unwrap().env_value(name)
+    }
+
+    /// A setting made with `hub.set`.
+    pub fn hub_setting(&self, name: &str) -> Option<Value> {
+        self.state.lock().unwrap().hub.get(name).cloned()
+    }
+
+    /// Add a note as Notehub would, e.g. to an inbound `.qi` notefile.
+    pub fn add_note(&self, file: &str, id: Option<&str>, body: Option<Value>, payload: Option<&str>) -> Option<Note> {
+        let mut state = self.state.lock().unwrap();
+        let time = state.now();
+        state
+            .notefiles
+            .entry(file.into())
+            .or_default()
+            .add(id, body, payload.map(Into::into), time)
+            .cloned()
+    }
+
+    /// Notes currently in `file`.
+    pub fn notes(&self, file: &str) -> Vec<Note> {
+        let state = self.state.lock().unwrap();
+        state
+            .notefiles
+            .get(file)
+            .map(|notefile| notefile.notes().to_vec())
+            .unwrap_or_default()
+    }
+}
+
+fn error_response(err: &str) -> String {
+    json!({ "err": err }).to_string()
+}
+
+fn str_arg<'a>(args: &'a Map<String, Value>, name: &str) -> Option<&'a str> {
+    args.get(name).and_then(Value::as_str)
+}
+
+fn bool_arg(args: &Map<String, Value>, name: &str) -> bool {
+    args.get(name).and_then(Value::as_bool).unwrap_or(false)
+}
+
+impl State {
+    fn now(&self) -> i64 {
+        self.time.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as i64)
+                .unwrap_or(0)
+        })
+    }
+
+    fn env_value(&self, name: &str) -> Option<String> {
+        self.env.get(name).or_else(|| self.env_defaults.get(name)).cloned()
+    }
+
+    fn handle(&mut self, name: &str, args: &Map<String, Value>) -> Result<Map<String, Value>, String> {
+        let time = self.now();
+        let mut response = Map::new();
+
+        match name {
+            "note.add" => {
+                let file = str_arg(args, "file").unwrap_or(DEFAULT_OUTBOUND_FILE);
+                let notefile = self.notefiles.entry(file.into()).or_default();
+                let payload = str_arg(args, "payload").map(Into::into);
+                notefile
+                    .add(str_arg(args, "note"), args.get("body").cloned(), payload, time)
+                    .ok_or_else(|| "note already exists {note-exists}".to_string())?;
+                response.insert("total".into(), notefile.notes().len().into());
+            }
+            "note.get" => {
+                let file = str_arg(args, "file").unwrap_or(DEFAULT_INBOUND_FILE);
+                let id = str_arg(args, "note");
+                let notefile = self
+                    .notefiles
+                    .get_mut(file)
+                    .ok_or_else(|| format!("notefile {file} does not exist {{file-noexist}}"))?;
+                let note = if bool_arg(args, "delete") {
+                    notefile.delete(id)
+                } else {
+                    notefile.get(id).cloned()
+                };
+                let note = note.ok_or_else(|| "note not found {note-noexist}".to_string())?;
+                response.insert("note".into(), note.id.clone().into());
+                response.extend(note.to_json());
+            }
+            "note.update" => {
+                let file = str_arg(args, "file").ok_or("no notefile specified")?;
+                let id = str_arg(args, "note").ok_or("no note ID specified")?;
+                let payload = str_arg(args, "payload").map(Into::into);
+                self.notefiles
+                    .get_mut(file)
+                    .and_then(|notefile| notefile.update(id, args.get("body").cloned(), payload, time))
+                    .ok_or_else(|| "note not found {note-noexist}".to_string())?;
+            }
+            "note.delete" => {
+                let file = str_arg(args, "file").ok_or("no notefile specified")?;
+                let id = str_arg(args, "note").ok_or("no note ID specified")?;
+                self.notefiles
+                    .get_mut(file)
+                    .and_then(|notefile| notefile.delete(Some(id)))
+                    .ok_or_else(|| "note not found {note-noexist}".to_string())?;
+            }
+            "note.changes" => {
+                let file = str_arg(args, "file").ok_or("no notefile specified")?;
+                let max = args
+                    .get("max")
+                    .and_then(Value::as_u64)
+                    .map_or(usize::MAX, |max| max as usize);
+                let start = bool_arg(args, "start") || bool_arg(args, "reset");
+                let changes = self.notefiles.entry(file.into()).or_default().changes(
+                    str_arg(args, "tracker"),
+                    max,
+                    start,
+                    bool_arg(args, "delete"),
+                );
+
+                response.insert("changes".into(), (changes.notes.len() + changes.remaining).into());
+                response.insert("total".into(), changes.total.into());
+                if !changes.notes.is_empty() {
+                    let notes: Map<String, Value> = changes
+                        .notes
+                        .iter()
+                        .map(|note| (note.id.clone(), Value::Object(note.to_json())))
+                        .collect();
+                    response.insert("notes".into(), Value::Object(notes));
+                }
+            }
+            "hub.set" => {
+                self.hub.extend(args.clone());
+            }
+            "hub.get" => {
+                response.insert("device".into(), DEVICE.into());
+                response.extend(self.hub.clone());
+            }
+            "env.set" | "env.default" => {
+                let var = str_arg(args, "name").ok_or("no variable name specified")?;
+                let vars = if name == "env.set" { &mut self.env } else { &mut self.env_defaults };
+                match str_arg(args, "text") {
+                    Some(text) => vars.insert(var.into(), text.into()),
+                    None => vars.remove(var),
+                };
+            }
+            "env.get" => match str_arg(args, "name") {
+                Some(name) => {
+                    if let Some(text) = self.env_value(name) {
+                        response.insert("text".into(), text.into());
+                    }
+                }
+                None => {
+                    let mut body: Map<String, Value> = self
+                        .env_defaults
+                        .iter()
+                        .map(|(name, text)| (name.clone(), text.clone().into()))
+                        .collect();
+                    body.extend(self.env.iter().map(|(name, text)| (name.clone(), text.clone().into())));
+                    response.insert("body".into(), Value::Object(body));
+                }
+            },
+            "card.time" => {
+                response.insert("time".into(), time.into());
+                response.insert("zone".into(), "UTC,Etc/UTC".into());
+                response.insert("minutes".into(), 0.into());
+            }
+            "card.version" => {
+                let version = format!("notecard-{FIRMWARE_MAJOR}.{FIRMWARE_MINOR}.{FIRMWARE_PATCH}");
+                response.insert("version".into(), format!("{version}.{FIRMWARE_BUILD}").into());
+                response.insert("device".into(), DEVICE.into());
+                response.insert("name".into(), "Blues Wireless Notecard (simulated)".into());
+                response.insert("board".into(), "sim".into());
+                response.insert("api".into(), 8.into());
+                response.insert(
+                    "body".into(),
+                    json!({
+                        "org": "Blues Wireless",
+                        "product": "Notecard",
+                        "version": version,
+                        "ver_major": FIRMWARE_MAJOR,
+                        "ver_minor": FIRMWARE_MINOR,
+                        "ver_patch": FIRMWARE_PATCH,
+                        "ver_build": FIRMWARE_BUILD,
+                        "built": "simulated",
+                    }),
+                );
+            }
+            _ => return Err(format!("unknown request: {name} {{not-supported}}")),
+        }
+
+        Ok(response)
+    }
+}
+
+/// Byte stream to a [`Simulator`], behaving like the Notecard UART.
+///
+/// Reads are pending, or report no data as ready, until a response is available.
+pub struct SimSerial {
+    simulator: Simulator,
+
+    // Request bytes received since the last newline
+    input: Vec<u8>,
+    // Response bytes not yet read
+    output: VecDeque<u8>,
+}
+
+impl SimSerial {
+    /// The simulated Notecard this stream is connected to.
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    fn read_output(&mut self, buf: &mut [u8]) -> usize {
+        let len = self.output.len().min(buf.len());
+        for (byte, out) in self.output.drain(..len).zip(buf.iter_mut()) {
+            *out = byte;
+        }
+        len
+    }
+
+    fn write_input(&mut self, buf: &[u8]) -> usize {
+        for byte in buf {
+            if *byte != b'\n' {
+                self.input.push(*byte);
+                continue;
+            }
+
+            let line = String::from_utf8_lossy(&self.input).into_owned();
+            self.input.clear();
+            let line = line.trim();
+            let response = if line.is_empty() {
+                // A bare newline is acknowledged with a bare `\r\n`
+                Some(String::new())
+            } else {
+                self.simulator.request(line)
+            };
+            if let Some(response) = response {
+                self.output.extend(response.as_bytes());
+                self.output.extend(b"\r\n");
+            }
+        }
+        buf.len()
+    }
+}
+
+impl ErrorType for SimSerial {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for SimSerial {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        std::future::poll_fn(|cx| {
+            if self.output.is_empty() && !buf.is_empty() {
+                cx.waker().wake_by_ref();
+                std::task::Poll::Pending
+            } else {
+                std::task::Poll::Ready(Ok(self.read_output(buf)))
+            }
+        })
+        .await
+    }
+}
+
+impl embedded_io_async::Write for SimSerial {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.write_input(buf))
+    }
+}
+
+impl embedded_io::Read for SimSerial {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.read_output(buf))
+    }
+}
+
+impl ReadReady for SimSerial {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(!self.output.is_empty())
+    }
+}
+
+impl embedded_io::Write for SimSerial {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.write_input(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+//! Notes and notefiles.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+
+/// A note stored in a notefile.
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub id: String,
+    pub body: Option<Value>,
+    pub payload: Option<String>,
+    pub time: i64,
+
+    // Change counter of the notefile when the note was last written
+    change: u64,
+}
+
+impl Note {
+    /// The note as returned by `note.get` and `note.changes`, without its id.
+    pub(crate) fn to_json(&self) -> Map<String, Value> {
+        let mut json = Map::new();
+        if let Some(body) = &self.body {
+            json.insert("body".into(), body.clone());
+        }
+        if let Some(payload) = &self.payload {
+            json.insert("payload".into(), payload.clone().into());
+        }
+        json.insert("time".into(), self.time.into());
+        json
+    }
+}
+
+/// Notes of a notefile in the order they were added.
+#[derive(Default)]
+pub(crate) struct Notefile {
+    notes: Vec<Note>,
+    next_id: u64,
+    change: u64,
+
+    // Change counter up to which each tracker has seen the notefile
+    trackers: BTreeMap<String, u64>,
+}
+
+/// Result of [`Notefile::changes`].
+pub(crate) struct Changes {
+    pub notes: Vec<Note>,
+    pub remaining: usize,
+    pub total: usize,
+}
+
+impl Notefile {
+    pub(crate) fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    pub(crate) fn get(&self, id: Option<&str>) -> Option<&Note> {
+        match id {
+            Some(id) => self.notes.iter().find(|note| note.id == id),
+            None => self.notes.first(),
+        }
+    }
+
+    /// Add a note, generating an id unless given one. Returns `None` when the id is taken.
+    pub(crate) fn add(
+        &mut self,
+        id: Option<&str>,
+        body: Option<Value>,
+        payload: Option<String>,
+        time: i64,
+    ) -> Option<&Note> {
+        let id = match id {
+            Some(id) if self.get(Some(id)).is_some() => return None,
+            Some(id) => id.into(),
+            None => {
+                self.next_id += 1;
+                self.next_id.to_string()
+            }
+        };
+
+        self.change += 1;
+        self.notes.push(Note {
+            id,
+            body,
+            payload,
+            time,
+            change: self.change,
+        });
+        self.notes.last()
+    }
+
+    /// Replace the body and payload of an existing note.
+    pub(crate) fn update(
+        &mut self,
+        id: &str,
+        body: Option<Value>,
+        payload: Option<String>,
+        time: i64,
+    ) -> Option<&Note> {
+        self.change += 1;
+        let change = self.change;
+        let note = self.notes.iter_mut().find(|note| note.id == id)?;
+        note.body = body;
+        note.payload = payload;
+        note.time = time;
+        note.change = change;
+        Some(note)
+    }
+
+    pub(crate) fn delete(&mut self, id: Option<&str>) -> Option<Note> {
+        let index = match id {
+            Some(id) => self.notes.iter().position(|note| note.id == id)?,
+            None if self.notes.is_empty() => return None,
+            None => 0,
+        };
+        Some(self.notes.remove(index))
+    }
+
+    /// Notes changed since `tracker` last looked, or all notes without a tracker.
+    ///
+    /// `start` rewinds the tracker first. Returned notes are removed when `delete` is set.
+    pub(crate) fn changes(&mut self, tracker: Option<&str>, max: usize, start: bool, delete: bool) -> Changes {
+        let seen = match tracker {
+            Some(tracker) if !start => self.trackers.get(tracker).copied().unwrap_or(0),
+            _ => 0,
+        };
+
+        let mut changed: Vec<&Note> = self.notes.iter().filter(|note| note.change > seen).collect();
+        changed.sort_by_key(|note| note.change);
+        let remaining = changed.len().saturating_sub(max);
+        let notes: Vec<Note> = changed.into_iter().take(max).cloned().collect();
+
+        if let (Some(tracker), Some(last)) = (tracker, notes.last()) {
+            self.trackers.insert(tracker.into(), last.change);
+        } else if let (Some(tracker), true) = (tracker, start) {
+            self.trackers.insert(tracker.into(), 0);
+        }
+
+        if delete {
+            self.notes.retain(|note| !notes.iter().any(|returned| returned.id == note.id));
+        }
+
+        Changes {
+            notes,
+            remaining,
+            total: self.notes.len(),
+        }
+    }
+}
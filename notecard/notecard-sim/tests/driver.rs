@@ -0,0 +1,181 @@
+//! Drives `Notecard` through `SimSerial`, end-to-end over the serial protocol.
+
+use blues_notecard_next::blocking::block_on;
+use blues_notecard_next::mock::MockDelay;
+use blues_notecard_next::note::req::{NoteAdd, NoteChanges, NoteGet};
+use blues_notecard_next::transport::Serial;
+use blues_notecard_next::{card, hub, Config, Error, FirmwareVersion, Notecard};
+use notecard_sim::{SimSerial, Simulator};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Reading {
+    temp: f32,
+    count: u32,
+}
+
+/// Discards the logs of test runs that build the driver with `defmt`, unused otherwise.
+#[defmt::global_logger]
+struct DiscardLogger;
+
+unsafe impl defmt::Logger for DiscardLogger {
+    fn acquire() {}
+    unsafe fn flush() {}
+    unsafe fn release() {}
+    unsafe fn write(_bytes: &[u8]) {}
+}
+
+defmt::timestamp!("{=u8}", 0);
+
+fn notecard(sim: &Simulator, config: Config) -> Notecard<Serial<SimSerial>, MockDelay> {
+    Notecard::new_with_config(Serial::new(sim.serial()), MockDelay::new(), config)
+}
+
+/// Every option that changes what goes over the wire.
+fn all_options() -> Config {
+    Config {
+        crc: true,
+        request_id: true,
+        detect_firmware: true,
+        ..Default::default()
+    }
+}
+
+/// Run `test` against a fresh simulator with each configuration, on the primary and the AUX UART.
+fn scenario(test: impl Fn(&Simulator, &mut Notecard<Serial<SimSerial>, MockDelay>)) {
+    let configs: [fn() -> Config; 2] = [Config::default, all_options];
+    let serials: [fn(SimSerial) -> Serial<SimSerial>; 2] = [Serial::new, Serial::new_aux];
+    for config in configs {
+        for serial in serials {
+            let sim = Simulator::new();
+            let mut note = Notecard::new_with_config(serial(sim.serial()), MockDelay::new(), config());
+            test(&sim, &mut note);
+        }
+    }
+}
+
+#[test]
+fn notes() {
+    scenario(|sim, note| {
+        for count in 0..3 {
+            let add = NoteAdd::new("sensors.qo", Reading { temp: 21.5, count });
+            block_on(note.transaction(add)).unwrap();
+        }
+        assert_eq!(sim.notes("sensors.qo").len(), 3);
+
+        let payload = "QUJD".repeat(200);
+        let add = NoteAdd {
+            note: Some("last"),
+            payload: Some(&payload),
+            ..NoteAdd::new("config.db", Reading { temp: 1.0, count: 7 })
+        };
+        block_on(note.transaction(add)).unwrap();
+        let last = block_on(note.transaction(NoteGet::<Reading>::new("config.db").with_note("last"))).unwrap();
+        assert_eq!(last.body, Some(Reading { temp: 1.0, count: 7 }));
+        assert_eq!(last.payload.as_deref(), Some(payload.as_str()));
+
+        // A payload larger than the chosen size does not fit
+        let small = block_on(note.transaction(NoteGet::<Reading, 256>::new("config.db").with_note("last")));
+        assert!(matches!(small, Err(Error::DeserError(_))));
+
+        let add = NoteAdd {
+            payload: Some(&payload),
+            ..NoteAdd::new("sensors.qo", Reading { temp: 21.5, count: 3 })
+        };
+        block_on(note.transaction(add)).unwrap();
+
+        let changes = block_on(note.transaction_ref(NoteChanges::<Reading>::new("sensors.qo", "host"))).unwrap();
+        let notes: Vec<_> = changes.notes().map(|note| note.unwrap().1).collect();
+        let counts: Vec<u32> = notes.iter().map(|note| note.body.as_ref().unwrap().count).collect();
+        assert_eq!(counts, [0, 1, 2, 3]);
+        assert_eq!(notes[3].payload.as_deref(), Some(payload.as_str()));
+
+        // The tracker has seen all notes now
+        let changes = block_on(note.transaction_ref(NoteChanges::<Reading>::new("sensors.qo", "host"))).unwrap();
+        assert_eq!(changes.notes().count(), 0);
+
+        let missing = block_on(note.transaction(NoteGet::<Reading>::new("config.db").with_note("first")));
+        assert!(matches!(missing, Err(Error::NoteNotFound(_))));
+    });
+}
+
+#[test]
+fn hub_settings() {
+    scenario(|sim, note| {
+        let set = r#"{"req":"hub.set","product":"com.example:test","mode":"continuous"}"#;
+        block_on(note.transaction_raw(set)).unwrap();
+        assert_eq!(sim.hub_setting("product").unwrap(), "com.example:test");
+
+        let hub = block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        assert!(matches!(hub.mode, Some(hub::req::HubMode::Continuous)));
+    });
+}
+
+#[test]
+fn env() {
+    scenario(|sim, note| {
+        block_on(note.transaction_raw(r#"{"req":"env.default","name":"interval","text":"60"}"#)).unwrap();
+        let response = block_on(note.transaction_raw_str(r#"{"req":"env.get","name":"interval"}"#)).unwrap();
+        assert!(response.contains(r#""text":"60""#), "{response}");
+
+        sim.set_env("interval", "5");
+        let response = block_on(note.transaction_raw_str(r#"{"req":"env.get","name":"interval"}"#)).unwrap();
+        assert!(response.contains(r#""text":"5""#), "{response}");
+    });
+}
+
+#[test]
+fn card_time() {
+    scenario(|sim, note| {
+        sim.set_time(1_700_000_000);
+
+        let time = block_on(note.transaction(card::req::CardTime::default())).unwrap();
+        assert_eq!(time.time.unwrap().timestamp(), 1_700_000_000);
+        assert_eq!(time.minutes, Some(0));
+    });
+}
+
+#[test]
+fn firmware_detection() {
+    let sim = Simulator::new();
+    let mut note = notecard(&sim, all_options());
+
+    block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+    assert_eq!(note.firmware_version(), Some(FirmwareVersion::new(8, 1, 3, 17004)));
+}
+
+#[test]
+fn aux_pacing() {
+    const REQUEST: &str = r#"{"req":"hub.set","product":"com.example:a-product-long-enough-to-be-paced"}"#;
+
+    // Time waited while sending a request after firmware detection ran
+    let pacing_ms = |serial: Serial<SimSerial>, config: Config| {
+        let delay = MockDelay::new();
+        let mut note = Notecard::new_with_config(serial, delay.clone(), config);
+        block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+        assert_eq!(note.firmware_version(), Some(FirmwareVersion::new(8, 1, 3, 17004)));
+
+        let before = delay.elapsed();
+        block_on(note.transaction_raw(REQUEST)).unwrap();
+        (delay.elapsed() - before).num_milliseconds()
+    };
+    let detect = || Config {
+        detect_firmware: true,
+        ..Default::default()
+    };
+    let sim = Simulator::new();
+
+    // Pacing is turned off on the AUX UART
+    assert_eq!(pacing_ms(Serial::new_aux(sim.serial()), detect()), 0);
+
+    // Pacing changed by the user is kept
+    let custom = Config {
+        segment_length: 60,
+        ..detect()
+    };
+    assert!(pacing_ms(Serial::new_aux(sim.serial()), custom) > 0);
+
+    // The primary UART keeps the default pacing
+    assert!(pacing_ms(Serial::new(sim.serial()), detect()) > 0);
+}
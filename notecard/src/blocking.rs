@@ -0,0 +1,358 @@
+//! Blocking Notecard driver.
+//!
+//! For firmware without an async executor, e.g. RTIC or a superloop. The driver takes any
+//! [`NotecardTransport`], [`BlockingIo`] and [`BlockingI2c`] adapt `embedded_io::{Read, Write,
+//! ReadReady}` interfaces and `embedded_hal::i2c::I2c` buses to the transports of the async
+//! driver. Delays are `embedded_hal::delay::DelayNs`.
+//!
+//! It wraps the async [`crate::Notecard`] so serialization, framing, retries and parsing are
+//! shared. Blocking delays are sliced into short steps while the interface is polled for data, so
+//! response timeouts work without a timer driver.
+//!
+//! ```rust, ignore
+//! let mut note = blocking::Notecard::new(Serial::new(BlockingIo::new(uart)), delay);
+//! let hub = note.transaction(hub::req::HubGet::default())?;
+//!
+//! let mut note = blocking::Notecard::new(I2cSerial::new(BlockingI2c::new(i2c)), delay);
+//! ```
+
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{ErrorType as I2cErrorType, I2c, Operation};
+use embedded_io::{ErrorType, Read, ReadReady, Write};
+use serde::Serialize;
+
+use crate::transport::NotecardTransport;
+use crate::{yield_now, Config, FirmwareVersion, NoteCommand, NoteTransaction, NoteTransactionRef, SuspendState, DEFAULT_BUF_SIZE};
+
+/// Granularity of blocking delays while waiting on the interface (ns).
+const DELAY_STEP_NS: u32 = 1_000_000;
+
+/// Poll a future to completion, busy looping while it is pending.
+///
+/// Drives the async driver on top of blocking interfaces, which never wake a waker. Host tests
+/// against the mock or the simulator use it the same way.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+/// Async view of a blocking UART for [`crate::transport::Serial`], reads are pending until data
+/// is ready.
+pub struct BlockingIo<IFT>(IFT);
+
+impl<IFT> BlockingIo<IFT> {
+    pub fn new(interface: IFT) -> Self {
+        Self(interface)
+    }
+
+    /// Release the interface.
+    pub fn release(self) -> IFT {
+        self.0
+    }
+}
+
+impl<IFT: ErrorType> ErrorType for BlockingIo<IFT> {
+    type Error = IFT::Error;
+}
+
+impl<IFT: Read + ReadReady> embedded_io_async::Read for BlockingIo<IFT> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        poll_fn(|cx| match self.0.read_ready() {
+            Ok(true) => Poll::Ready(self.0.read(buf)),
+            Ok(false) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Err(err) => Poll::Ready(Err(err)),
+        })
+        .await
+    }
+}
+
+impl<IFT: Write> embedded_io_async::Write for BlockingIo<IFT> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.write(buf)
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+}
+
+/// Async view of a blocking I2C bus for [`crate::i2c::I2cSerial`].
+pub struct BlockingI2c<I2C>(I2C);
+
+impl<I2C> BlockingI2c<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self(i2c)
+    }
+
+    /// Release the I2C bus.
+    pub fn release(self) -> I2C {
+        self.0
+    }
+}
+
+impl<I2C: I2cErrorType> I2cErrorType for BlockingI2c<I2C> {
+    type Error = I2C::Error;
+}
+
+impl<I2C: I2c> embedded_hal_async::i2c::I2c for BlockingI2c<I2C> {
+    async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+        self.0.transaction(address, operations)
+    }
+}
+
+/// Async view of a blocking delay, yielding every `DELAY_STEP_NS`.
+pub(crate) struct BlockingDelay<D>(D);
+
+impl<D: DelayNs> embedded_hal_async::delay::DelayNs for BlockingDelay<D> {
+    async fn delay_ns(&mut self, ns: u32) {
+        let mut remaining = ns;
+        while remaining > 0 {
+            let step = remaining.min(DELAY_STEP_NS);
+            self.0.delay_ns(step);
+            remaining -= step;
+            yield_now().await;
+        }
+    }
+}
+
+/// Blocking Notecard driver.
+///
+/// See [`crate::Notecard`], the buffer is configured the same way.
+pub struct Notecard<
+    IFT: NotecardTransport,
+    D: DelayNs,
+    const BUF_SIZE: usize = DEFAULT_BUF_SIZE,
+    S: AsRef<[u8]> + AsMut<[u8]> = [u8; BUF_SIZE],
+> {
+    inner: crate::Notecard<IFT, BlockingDelay<D>, BUF_SIZE, S>,
+}
+
+/// Blocking Notecard driver using a borrowed buffer.
+pub type StaticNotecard<IFT, D> = Notecard<IFT, D, 0, &'static mut [u8]>;
+
+impl<IFT: NotecardTransport, D: DelayNs> Notecard<IFT, D> {
+    /// Create a new Notecard driver handler with the default configuration
+    pub fn new(transport: IFT, delay: D) -> Self {
+        Self::new_sized(transport, delay)
+    }
+
+    /// Create a new Notecard driver with a custom configuration
+    pub fn new_with_config(transport: IFT, delay: D, config: Config) -> Self {
+        Self::new_sized_with_config(transport, delay, config)
+    }
+
+    /// Recreate the driver from an existing state and a transport.
+    pub fn resume(transport: IFT, delay: D, state: SuspendState) -> Self {
+        Self::resume_sized(transport, delay, state)
+    }
+}
+
+impl<IFT: NotecardTransport, D: DelayNs, const BUF_SIZE: usize> Notecard<IFT, D, BUF_SIZE> {
+    /// Create a new Notecard driver with a `BUF_SIZE` bytes buffer
+    pub fn new_sized(transport: IFT, delay: D) -> Self {
+        Self::new_sized_with_config(transport, delay, Config::default())
+    }
+
+    /// Create a new Notecard driver with a `BUF_SIZE` bytes buffer and a custom configuration
+    pub fn new_sized_with_config(transport: IFT, delay: D, config: Config) -> Self {
+        Self {
+            inner: crate::Notecard::new_sized_with_config(transport, BlockingDelay(delay), config),
+        }
+    }
+
+    /// Release the transport by returning it with the driver state and consuming the driver.
+    pub fn suspend(self) -> (IFT, SuspendState) {
+        self.inner.suspend()
+    }
+
+    /// Recreate the driver with a `BUF_SIZE` bytes buffer from an existing state and a transport.
+    pub fn resume_sized(transport: IFT, delay: D, state: SuspendState) -> Self {
+        Self {
+            inner: crate::Notecard::resume_sized(transport, BlockingDelay(delay), state),
+        }
+    }
+}
+
+impl<IFT: NotecardTransport, D: DelayNs> StaticNotecard<IFT, D> {
+    /// Create a new Notecard driver using `buffer` for requests and responses
+    pub fn new_with_buffer(transport: IFT, delay: D, buffer: &'static mut [u8]) -> Self {
+        Self::new_with_buffer_and_config(transport, delay, buffer, Config::default())
+    }
+
+    /// Create a new Notecard driver using `buffer` for requests and responses with a custom
+    /// configuration
+    pub fn new_with_buffer_and_config(
+        transport: IFT,
+        delay: D,
+        buffer: &'static mut [u8],
+        config: Config,
+    ) -> Self {
+        Self {
+            inner: crate::StaticNotecard::new_with_buffer_and_config(
+                transport,
+                BlockingDelay(delay),
+                buffer,
+                config,
+            ),
+        }
+    }
+
+    /// Release the transport and buffer by returning them with the driver state and consuming
+    /// the driver.
+    pub fn suspend(self) -> (IFT, SuspendState, &'static mut [u8]) {
+        self.inner.suspend()
+    }
+
+    /// Recreate the driver from an existing state, a transport and a buffer.
+    pub fn resume_with_buffer(
+        transport: IFT,
+        delay: D,
+        state: SuspendState,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Self {
+            inner: crate::StaticNotecard::resume_with_buffer(
+                transport,
+                BlockingDelay(delay),
+                state,
+                buffer,
+            ),
+        }
+    }
+}
+
+impl<IFT: NotecardTransport, D: DelayNs, const BUF_SIZE: usize, S: AsRef<[u8]> + AsMut<[u8]>>
+    Notecard<IFT, D, BUF_SIZE, S>
+{
+    /// Execute a json transaction
+    ///
+    /// See [`crate::Notecard::transaction`].
+    pub fn transaction<T: Serialize + NoteTransaction>(&mut self, cmd: T) -> Result<<T as NoteTransaction>::NoteResult, crate::Error> {
+        block_on(self.inner.transaction(cmd))
+    }
+
+    /// Execute a json transaction whose result borrows from the response
+    ///
+    /// See [`crate::Notecard::transaction_ref`].
+    pub fn transaction_ref<T: Serialize + NoteTransactionRef>(&mut self, cmd: T) -> Result<T::NoteResult<'_>, crate::Error> {
+        block_on(self.inner.transaction_ref(cmd))
+    }
+
+    /// Send a command, a request the Notecard does not respond to
+    ///
+    /// See [`crate::Notecard::command`].
+    pub fn command<T: NoteCommand>(&mut self, cmd: T) -> Result<(), crate::Error> {
+        block_on(self.inner.command(cmd))
+    }
+
+    /// Execute a transaction from a raw JSON request and return the response line
+    ///
+    /// See [`crate::Notecard::transaction_raw`].
+    pub fn transaction_raw(&mut self, req: &str) -> Result<&[u8], crate::Error> {
+        block_on(self.inner.transaction_raw(req))
+    }
+
+    /// Execute a transaction from a raw JSON request and return the response line as a string
+    ///
+    /// See [`crate::Notecard::transaction_raw_str`].
+    pub fn transaction_raw_str(&mut self, req: &str) -> Result<&str, crate::Error> {
+        block_on(self.inner.transaction_raw_str(req))
+    }
+
+    /// Reset the Notecard
+    pub fn reset(&mut self) -> Result<(), crate::Error> {
+        block_on(self.inner.reset())
+    }
+
+    /// Firmware version of the Notecard, if detected.
+    pub fn firmware_version(&self) -> Option<FirmwareVersion> {
+        self.inner.firmware_version()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::error::{Error, TransientError};
+    use crate::hub;
+    use crate::i2c::tests::MockI2c;
+    use crate::i2c::I2cSerial;
+    use crate::mock::{MockDelay, MockSerial};
+    use crate::transport::Serial;
+
+    #[test]
+    fn transaction() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"mode":"periodic","product":"com.example:test"}"#);
+
+        let mut note = Notecard::new(Serial::new(BlockingIo::new(mock.clone())), MockDelay::new());
+        let hub = note.transaction(hub::req::HubGet::default()).unwrap();
+
+        assert!(matches!(hub.mode, Some(hub::req::HubMode::Periodic)));
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn transaction_timeout() {
+        let mock = MockSerial::new();
+        mock.expect_reset().expect_request(r#"{"req":"hub.get"}"#);
+
+        let delay = MockDelay::new();
+        let config = Config {
+            transaction_retry: 0,
+            ..Default::default()
+        };
+        let mut note = Notecard::new_with_config(Serial::new(BlockingIo::new(mock.clone())), delay.clone(), config);
+        let result = note.transaction(hub::req::HubGet::default());
+
+        assert!(matches!(
+            result,
+            Err(Error::RetriesExhausted {
+                attempts: 1,
+                last: TransientError::TimeOut
+            })
+        ));
+        assert!(delay.elapsed() >= Duration::seconds(5));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn i2c_transaction() {
+        let request = b"{\"req\":\"hub.get\"}\n";
+        let mut frame = std::vec![request.len() as u8];
+        frame.extend_from_slice(request);
+        let response = br#"{"product":"com.example:test"}"#;
+        let i2c = MockI2c::default()
+            .write(&[1, b'\n'])
+            .query(2)
+            .frame(b"\r\n", 0)
+            .write(&frame)
+            .query(response.len() as u8)
+            .frame(response, 2)
+            .frame(b"\r\n", 0);
+
+        let mut note = Notecard::new(I2cSerial::new(BlockingI2c::new(i2c)), MockDelay::new());
+        let hub = note.transaction(hub::req::HubGet::default()).unwrap();
+
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        let (transport, _) = note.suspend();
+        transport.release().release().assert_done();
+    }
+}
@@ -0,0 +1,66 @@
+//! Request and response buffer backed by either an owned array or a borrowed slice.
+
+use core::ops::Deref;
+
+/// A byte vector on top of a fixed size storage.
+pub(crate) struct Buffer<S> {
+    storage: S,
+    len: usize,
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> Buffer<S> {
+    pub(crate) fn new(storage: S) -> Self {
+        Self { storage, len: 0 }
+    }
+
+    pub(crate) fn into_storage(self) -> S {
+        self.storage
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.storage.as_ref().len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<u8> {
+        let last = self.last().copied()?;
+        self.len -= 1;
+        Some(last)
+    }
+
+    pub(crate) fn push(&mut self, byte: u8) -> Result<(), ()> {
+        self.extend_from_slice(&[byte])
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, data: &[u8]) -> Result<(), ()> {
+        let end = self.len + data.len();
+        if end > self.capacity() {
+            return Err(());
+        }
+
+        self.storage.as_mut()[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Unused space after the content, to be committed with `advance`.
+    pub(crate) fn spare_capacity_mut(&mut self) -> &mut [u8] {
+        &mut self.storage.as_mut()[self.len..]
+    }
+
+    /// Extend the content by `count` bytes written to `spare_capacity_mut`.
+    pub(crate) fn advance(&mut self, count: usize) {
+        self.len = (self.len + count).min(self.capacity());
+    }
+}
+
+impl<S: AsRef<[u8]>> Deref for Buffer<S> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.storage.as_ref()[..self.len]
+    }
+}
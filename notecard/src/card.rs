@@ -0,0 +1,644 @@
+use serde::{Serialize, Deserialize};
+
+pub mod req {
+
+    use super::*;
+    use crate::{NoteCommand, NoteTransaction};
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Version)]
+    pub struct CardVersion {
+        pub req: &'static str
+    }
+
+    impl Default for CardVersion {
+        fn default() -> Self {
+            Self {
+                req: "card.version"
+            }
+        }
+    }
+
+    /// Restart the Notecard, sent as a command since it does not respond.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteCommand)]
+    pub struct CardRestart {
+        pub req: &'static str
+    }
+
+    impl Default for CardRestart {
+        fn default() -> Self {
+            Self {
+                req: "card.restart"
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Status)]
+    pub struct CardStatus {
+        pub req: &'static str
+    }
+
+    impl Default for CardStatus {
+        fn default() -> Self {
+            Self {
+                req: "card.status"
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Time)]
+    pub struct CardTime {
+        pub req: &'static str
+    }
+
+    impl Default for CardTime {
+        fn default() -> Self {
+            Self {
+                req: "card.time"
+            }
+        }
+    }
+
+    /// Read the temperature of the Notecard, or configure temperature monitoring.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Temp)]
+    pub struct CardTemp<'a> {
+        pub req: &'static str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub minutes: Option<u32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub status: Option<&'a str>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub stop: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sync: Option<bool>,
+    }
+
+    impl <'a> Default for CardTemp<'a> {
+        fn default() -> Self {
+            Self {
+                req: "card.temp",
+                minutes: Default::default(),
+                status: Default::default(),
+                stop: Default::default(),
+                sync: Default::default(),
+            }
+        }
+    }
+
+    /// Battery chemistry used to derive the voltage thresholds.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[serde(rename_all = "lowercase")]
+    pub enum VoltageMode {
+        Default,
+        Lipo,
+        L91,
+        Alkaline,
+        Tad,
+        Lic,
+        /// Query the current thresholds.
+        #[serde(rename = "?")]
+        Query,
+    }
+
+    /// Read the supply voltage, or configure the voltage thresholds and monitoring.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Voltage)]
+    pub struct CardVoltage {
+        pub req: &'static str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub hours: Option<u32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub offset: Option<u32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub vmax: Option<f32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub vmin: Option<f32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub mode: Option<VoltageMode>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub usb: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub alert: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sync: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub calibration: Option<f32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub set: Option<bool>,
+    }
+
+    impl Default for CardVoltage {
+        fn default() -> Self {
+            Self {
+                req: "card.voltage",
+                hours: Default::default(),
+                offset: Default::default(),
+                vmax: Default::default(),
+                vmin: Default::default(),
+                mode: Default::default(),
+                usb: Default::default(),
+                alert: Default::default(),
+                sync: Default::default(),
+                calibration: Default::default(),
+                set: Default::default(),
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Location)]
+    pub struct CardLocation {
+        pub req: &'static str
+    }
+
+    impl Default for CardLocation {
+        fn default() -> Self {
+            Self {
+                req: "card.location"
+            }
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[serde(rename_all = "lowercase")]
+    pub enum LocationMode {
+        Off,
+        Periodic,
+        Continuous,
+        /// Report the `lat` and `lon` set with the mode instead of sampling the GPS.
+        Fixed,
+    }
+
+    /// Set the GPS mode, without `mode` the current mode is returned.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::LocationModeResult)]
+    pub struct CardLocationMode<'a> {
+        pub req: &'static str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub mode: Option<LocationMode>,
+
+        /// Seconds between samples in periodic mode.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub seconds: Option<u32>,
+
+        /// Sampling interval by voltage state, e.g. `"usb:3600;normal:7200"`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub vseconds: Option<&'a str>,
+
+        /// Remove the geofence.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delete: Option<bool>,
+
+        /// Radius of the geofence around `lat` and `lon` in meters.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub max: Option<u32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub lat: Option<f64>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub lon: Option<f64>,
+
+        /// Minutes the Notecard must be outside the geofence before it reports it.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub minutes: Option<u32>,
+
+        /// Number of motion events that trigger a sample in periodic mode.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub threshold: Option<u32>,
+    }
+
+    impl <'a> CardLocationMode<'a> {
+        pub fn new(mode: LocationMode) -> Self {
+            Self {
+                mode: Some(mode),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl <'a> Default for CardLocationMode<'a> {
+        fn default() -> Self {
+            Self {
+                req: "card.location.mode",
+                mode: Default::default(),
+                seconds: Default::default(),
+                vseconds: Default::default(),
+                delete: Default::default(),
+                max: Default::default(),
+                lat: Default::default(),
+                lon: Default::default(),
+                minutes: Default::default(),
+                threshold: Default::default(),
+            }
+        }
+    }
+
+    /// Start or stop tracking, which stores the samples of the GPS as notes.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::LocationTrack)]
+    pub struct CardLocationTrack<'a> {
+        pub req: &'static str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub start: Option<bool>,
+
+        /// Add a note every `hours` while the Notecard is not moving.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub heartbeat: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub hours: Option<u32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sync: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub stop: Option<bool>,
+
+        /// Notefile of the tracking notes, `_track.qo` by default.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub file: Option<&'a str>,
+    }
+
+    impl <'a> Default for CardLocationTrack<'a> {
+        fn default() -> Self {
+            Self {
+                req: "card.location.track",
+                start: Default::default(),
+                heartbeat: Default::default(),
+                hours: Default::default(),
+                sync: Default::default(),
+                stop: Default::default(),
+                file: Default::default(),
+            }
+        }
+    }
+}
+
+pub mod res {
+    use chrono::{DateTime, Utc};
+    use serde::Deserializer;
+
+    use super::*;
+    use crate::FirmwareVersion;
+
+    /// Deserialize seconds since the unix epoch, as the Notecard reports times.
+    fn epoch<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        let seconds: Option<i64> = Option::deserialize(deserializer)?;
+        seconds
+            .map(|seconds| {
+                DateTime::from_timestamp(seconds, 0)
+                    .ok_or_else(|| serde::de::Error::custom("time out of range"))
+            })
+            .transpose()
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct VersionBody {
+        pub org: Option<heapless::String<40>>,
+        pub product: Option<heapless::String<40>>,
+        pub version: Option<heapless::String<40>>,
+        pub ver_major: Option<u32>,
+        pub ver_minor: Option<u32>,
+        pub ver_patch: Option<u32>,
+        pub ver_build: Option<u32>,
+        pub built: Option<heapless::String<40>>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Version {
+        pub body: Option<VersionBody>,
+        pub version: Option<heapless::String<40>>,
+        pub device: Option<heapless::String<40>>,
+        pub name: Option<heapless::String<120>>,
+        pub sku: Option<heapless::String<40>>,
+        pub board: Option<heapless::String<40>>,
+        pub api: Option<u32>,
+    }
+
+    impl Version {
+        /// Firmware version from the structured `body` fields.
+        pub fn firmware_version(&self) -> Option<FirmwareVersion> {
+            let body = self.body.as_ref()?;
+            Some(FirmwareVersion::new(
+                body.ver_major?,
+                body.ver_minor?,
+                body.ver_patch.unwrap_or(0),
+                body.ver_build.unwrap_or(0),
+            ))
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Status {
+        pub status: Option<heapless::String<40>>,
+        pub usb: Option<bool>,
+        pub storage: Option<u32>,
+        #[serde(default, deserialize_with = "epoch")]
+        #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+        pub time: Option<DateTime<Utc>>,
+        pub connected: Option<bool>,
+        pub cell: Option<bool>,
+        pub wifi: Option<bool>,
+        pub gps: Option<bool>,
+        pub sync: Option<bool>,
+        pub inbound: Option<u32>,
+        pub outbound: Option<u32>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Time {
+        /// Unset until the Notecard has synced its clock with Notehub.
+        #[serde(default, deserialize_with = "epoch")]
+        #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+        pub time: Option<DateTime<Utc>>,
+        pub area: Option<heapless::String<120>>,
+        pub zone: Option<heapless::String<120>>,
+        /// Offset of the local time zone from UTC.
+        pub minutes: Option<i32>,
+        pub lat: Option<f64>,
+        pub lon: Option<f64>,
+        pub country: Option<heapless::String<40>>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Temp {
+        /// Degrees Celsius.
+        pub value: Option<f32>,
+        pub calibration: Option<f32>,
+    }
+
+    /// Supply state derived from the voltage thresholds.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[serde(rename_all = "lowercase")]
+    pub enum VoltageState {
+        Usb,
+        High,
+        Normal,
+        Low,
+        Dead,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Voltage {
+        pub mode: Option<VoltageState>,
+        pub usb: Option<bool>,
+        /// Volts.
+        pub value: Option<f32>,
+        pub hours: Option<u32>,
+        pub vmin: Option<f32>,
+        pub vmax: Option<f32>,
+        pub vavg: Option<f32>,
+        pub daily: Option<f32>,
+        pub weekly: Option<f32>,
+        pub monthly: Option<f32>,
+    }
+
+    /// A position in degrees.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Position {
+        pub lat: f64,
+        pub lon: f64,
+    }
+
+    impl Position {
+        /// Whether the position is inside the box spanned by its `south_west` and `north_east`
+        /// corners.
+        ///
+        /// A box with the western corner east of the eastern one crosses the antimeridian.
+        pub fn is_within(&self, south_west: Position, north_east: Position) -> bool {
+            let lat = (south_west.lat..=north_east.lat).contains(&self.lat);
+            let lon = if south_west.lon <= north_east.lon {
+                (south_west.lon..=north_east.lon).contains(&self.lon)
+            } else {
+                self.lon >= south_west.lon || self.lon <= north_east.lon
+            };
+            lat && lon
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Location {
+        pub status: Option<heapless::String<256>>,
+        pub mode: Option<self::req::LocationMode>,
+        pub lat: Option<f64>,
+        pub lon: Option<f64>,
+        /// Time of the fix.
+        #[serde(default, deserialize_with = "epoch")]
+        #[cfg_attr(feature = "defmt", defmt(Debug2Format))]
+        pub time: Option<DateTime<Utc>>,
+        /// Radius of the geofence in meters.
+        pub max: Option<u32>,
+        pub dop: Option<f32>,
+        pub journey: Option<u32>,
+        pub jcount: Option<u32>,
+        pub velocity: Option<f32>,
+        pub bearing: Option<f32>,
+        pub distance: Option<f32>,
+    }
+
+    impl Location {
+        /// Position of the last fix, if the Notecard has one.
+        pub fn position(&self) -> Option<Position> {
+            Some(Position {
+                lat: self.lat?,
+                lon: self.lon?,
+            })
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct LocationModeResult {
+        pub mode: Option<self::req::LocationMode>,
+        pub seconds: Option<u32>,
+        pub vseconds: Option<heapless::String<120>>,
+        pub max: Option<u32>,
+        pub lat: Option<f64>,
+        pub lon: Option<f64>,
+        pub minutes: Option<u32>,
+        pub threshold: Option<u32>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct LocationTrack {
+        pub start: Option<bool>,
+        pub stop: Option<bool>,
+        pub heartbeat: Option<bool>,
+        pub seconds: Option<u32>,
+        pub hours: Option<u32>,
+        pub file: Option<heapless::String<120>>,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+
+    use super::req::VoltageMode;
+    use super::res::*;
+
+    fn parse<'a, T: serde::Deserialize<'a>>(response: &'a str) -> Result<T, serde_json_core::de::Error> {
+        serde_json_core::from_str::<T>(response).map(|(value, _)| value)
+    }
+
+    fn position(lat: f64, lon: f64) -> Position {
+        Position { lat, lon }
+    }
+
+    #[test]
+    fn within_box() {
+        let (south_west, north_east) = (position(59.0, 10.0), position(60.0, 11.0));
+
+        assert!(position(59.9, 10.7).is_within(south_west, north_east));
+        assert!(!position(58.9, 10.7).is_within(south_west, north_east));
+        assert!(!position(59.9, 11.1).is_within(south_west, north_east));
+        assert!(!position(59.9, -10.7).is_within(south_west, north_east));
+    }
+
+    #[test]
+    fn within_box_across_antimeridian() {
+        let (south_west, north_east) = (position(-20.0, 170.0), position(-10.0, -170.0));
+
+        assert!(position(-15.0, 175.0).is_within(south_west, north_east));
+        assert!(position(-15.0, -175.0).is_within(south_west, north_east));
+        assert!(position(-15.0, 180.0).is_within(south_west, north_east));
+        assert!(position(-15.0, -180.0).is_within(south_west, north_east));
+        assert!(!position(-15.0, 0.0).is_within(south_west, north_east));
+        assert!(!position(-15.0, 160.0).is_within(south_west, north_east));
+        assert!(!position(-25.0, 175.0).is_within(south_west, north_east));
+    }
+
+    #[test]
+    fn within_box_on_edges() {
+        let (south_west, north_east) = (position(59.0, 10.0), position(60.0, 11.0));
+
+        assert!(south_west.is_within(south_west, north_east));
+        assert!(north_east.is_within(south_west, north_east));
+        assert!(position(59.0, 11.0).is_within(south_west, north_east));
+        assert!(position(60.0, 10.5).is_within(south_west, north_east));
+
+        let (south_west, north_east) = (position(-20.0, 170.0), position(-10.0, -170.0));
+        assert!(position(-20.0, 170.0).is_within(south_west, north_east));
+        assert!(position(-10.0, -170.0).is_within(south_west, north_east));
+    }
+
+    #[test]
+    fn status() {
+        let status: Status = parse(concat!(
+            r#"{"status":"{normal}","usb":true,"storage":8,"time":1599684765,"#,
+            r#""connected":true,"cell":true,"sync":true,"inbound":60,"outbound":360}"#,
+        ))
+        .unwrap();
+
+        assert_eq!(status.status.as_deref(), Some("{normal}"));
+        assert_eq!(status.usb, Some(true));
+        assert_eq!(status.storage, Some(8));
+        assert_eq!(status.time, DateTime::<Utc>::from_timestamp(1599684765, 0));
+        assert_eq!(status.connected, Some(true));
+        assert_eq!(status.wifi, None);
+        assert_eq!(status.inbound, Some(60));
+        assert_eq!(status.outbound, Some(360));
+    }
+
+    #[test]
+    fn status_without_time() {
+        let status: Status = parse(r#"{"status":"{normal}","usb":true,"storage":8}"#).unwrap();
+
+        assert_eq!(status.time, None);
+    }
+
+    #[test]
+    fn status_time_out_of_range() {
+        assert!(parse::<Status>(r#"{"time":9223372036854775807}"#).is_err());
+    }
+
+    #[test]
+    fn voltage() {
+        let voltage: Voltage =
+            parse(r#"{"usb":true,"hours":707,"mode":"usb","value":5.112190219747135,"vmin":4.8,"vmax":5.2,"vavg":5.1}"#)
+                .unwrap();
+
+        assert!(matches!(voltage.mode, Some(VoltageState::Usb)));
+        assert_eq!(voltage.usb, Some(true));
+        assert_eq!(voltage.hours, Some(707));
+        assert!((voltage.value.unwrap() - 5.112).abs() < 0.001);
+        assert_eq!(voltage.vmin, Some(4.8));
+        assert_eq!(voltage.daily, None);
+
+        let voltage: Voltage = parse(r#"{"mode":"low","value":3.2}"#).unwrap();
+        assert!(matches!(voltage.mode, Some(VoltageState::Low)));
+    }
+
+    #[test]
+    fn voltage_unknown_mode() {
+        assert!(parse::<Voltage>(r#"{"mode":"charging","value":4.1}"#).is_err());
+    }
+
+    #[test]
+    fn voltage_mode() {
+        let mut buffer = [0; 8];
+        let len = serde_json_core::to_slice(&VoltageMode::Query, &mut buffer).unwrap();
+        assert_eq!(&buffer[..len], br#""?""#);
+
+        assert!(matches!(parse::<VoltageMode>(r#""l91""#), Ok(VoltageMode::L91)));
+        assert!(parse::<VoltageMode>(r#""nimh""#).is_err());
+    }
+
+    #[test]
+    fn temp() {
+        let temp: Temp = parse(r#"{"value":27.625,"calibration":-3.0}"#).unwrap();
+
+        assert_eq!(temp.value, Some(27.625));
+        assert_eq!(temp.calibration, Some(-3.0));
+    }
+}
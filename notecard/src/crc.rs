@@ -0,0 +1,151 @@
+//! Request integrity checking compatible with `note-c`.
+//!
+//! A `"crc":"SSSS:CCCCCCCC"` field is appended to every request, where `SSSS` is the hex encoded
+//! sequence number and `CCCCCCCC` the hex encoded CRC-32 of the request JSON without the field. A
+//! Notecard supporting this echoes the field on its response, computed the same way over the
+//! response JSON.
+//!
+//! > `note-c`: https://github.com/blues/note-c/blob/master/n_request.c (`crcAdd`, `crcError`)
+
+use heapless::Vec;
+
+use crate::buffer::Buffer;
+use crate::error::Error;
+
+/// `"crc":"` prefix of the field.
+const CRC_FIELD_NAME: &[u8] = b"\"crc\":\"";
+
+/// Length of `"crc":"SSSS:CCCCCCCC"` without the leading separator.
+const CRC_FIELD_LENGTH: usize = CRC_FIELD_NAME.len() + 4 + 1 + 8 + 1;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+/// Update a CRC-32 (IEEE 802.3) with `data`.
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn hex<const N: usize>(value: u32) -> [u8; N] {
+    let mut digits = [0_u8; N];
+    for (i, digit) in digits.iter_mut().enumerate() {
+        *digit = HEX_DIGITS[((value >> ((N - 1 - i) * 4)) & 0xF) as usize];
+    }
+    digits
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u32> {
+    let digits = core::str::from_utf8(digits).ok()?;
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// Append the crc field to the JSON object in `buffer`.
+///
+/// `buffer` must hold exactly one serialized JSON object without the terminating newline.
+pub(crate) fn add_crc<S: AsRef<[u8]> + AsMut<[u8]>>(buffer: &mut Buffer<S>, seqno: u16) -> Result<(), Error> {
+    if buffer.len() < 2 || buffer.last() != Some(&b'}') {
+        return Err(Error::InvalidRequest);
+    }
+
+    let crc = crc32_update(0, buffer);
+    let empty = buffer[buffer.len() - 2] == b'{';
+
+    let mut field: Vec<u8, { CRC_FIELD_LENGTH + 2 }> = Vec::new();
+    if !empty {
+        field.push(b',').ok();
+    }
+    field.extend_from_slice(CRC_FIELD_NAME).ok();
+    field.extend_from_slice(&hex::<4>(seqno as u32)).ok();
+    field.push(b':').ok();
+    field.extend_from_slice(&hex::<8>(crc)).ok();
+    field.extend_from_slice(b"\"}").ok();
+
+    // Replace the closing brace with the field
+    buffer.pop();
+    buffer.extend_from_slice(&field).map_err(|_| Error::SerError)
+}
+
+/// Validate the crc field echoed on a response.
+///
+/// Responses without the field are accepted, older Notecard firmware does not send it.
+pub(crate) fn check_crc(response: &[u8], seqno: u16) -> Result<(), Error> {
+    let trimmed = response.trim_ascii_end();
+    let len = trimmed.len();
+    if len < CRC_FIELD_LENGTH + 2 || trimmed[len - 1] != b'}' {
+        return Ok(());
+    }
+
+    let field_start = len - 1 - CRC_FIELD_LENGTH;
+    let field = &trimmed[field_start..len - 1];
+    if !field.starts_with(CRC_FIELD_NAME) {
+        return Ok(());
+    }
+
+    // The JSON the crc was computed over ends right before the field and its separator
+    let json = match trimmed[field_start - 1] {
+        b',' => &trimmed[..field_start - 1],
+        b'{' => &trimmed[..field_start],
+        _ => return Ok(()),
+    };
+
+    let value = &field[CRC_FIELD_NAME.len()..];
+    let actual_seqno = parse_hex(&value[..4]);
+    let actual_crc = parse_hex(&value[5..13]);
+    let expected_crc = crc32_update(crc32_update(0, json), b"}");
+
+    if value[4] != b':' || actual_seqno != Some(seqno as u32) || actual_crc != Some(expected_crc) {
+        return Err(Error::CrcMismatch);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_crc(json: &[u8], seqno: u16) -> Result<std::vec::Vec<u8>, Error> {
+        let mut buffer = Buffer::new([0_u8; 64]);
+        buffer.extend_from_slice(json).unwrap();
+        add_crc(&mut buffer, seqno)?;
+        Ok(buffer.to_vec())
+    }
+
+    #[test]
+    fn crc32() {
+        assert_eq!(crc32_update(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32_update(0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(with_crc(b"{}", 1).unwrap(), br#"{"crc":"0001:A3A6BF43"}"#);
+        assert_eq!(
+            with_crc(br#"{"req":"hub.get"}"#, 0xBEEF).unwrap(),
+            br#"{"req":"hub.get","crc":"BEEF:E37F7D23"}"#
+        );
+        assert!(matches!(with_crc(b"[]", 1), Err(Error::InvalidRequest)));
+    }
+
+    #[test]
+    fn check() {
+        let response = br#"{"total":1,"crc":"0002:15CB2833"}"#;
+        assert!(check_crc(response, 2).is_ok());
+        assert!(check_crc(b"{\"total\":1,\"crc\":\"0002:15CB2833\"}\r\n", 2).is_ok());
+        assert!(check_crc(br#"{"crc":"0001:A3A6BF43"}"#, 1).is_ok());
+
+        assert!(matches!(check_crc(response, 3), Err(Error::CrcMismatch)));
+        assert!(matches!(check_crc(br#"{"total":2,"crc":"0002:15CB2833"}"#, 2), Err(Error::CrcMismatch)));
+        assert!(matches!(check_crc(br#"{"total":1,"crc":"0002:15CB283X"}"#, 2), Err(Error::CrcMismatch)));
+
+        // Older firmware does not echo the field
+        assert!(check_crc(br#"{"total":1}"#, 2).is_ok());
+    }
+}
@@ -1,13 +1,37 @@
+use core::fmt;
+
 use heapless::String;
+use serde::Deserialize;
+
+/// Fields every Notecard response may carry, checked before the response is parsed.
+///
+/// `err` is borrowed so a message of any length is recognized, it is cut off when it is stored.
+#[derive(Deserialize)]
+struct ResponseEnvelope<'a> {
+    err: Option<&'a str>,
+}
 
-#[derive(Debug, defmt::Format, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
-    WriteError,
+    /// Writing to the interface failed.
+    WriteError(embedded_io::ErrorKind),
 
-    ReadError,
+    /// Reading from the interface failed.
+    ReadError(embedded_io::ErrorKind),
 
+    /// I2C bus transfer failed.
+    I2cError(embedded_hal::i2c::ErrorKind),
+
+    /// Response is valid JSON but does not match the expected result type.
+    ///
+    /// Not retried, the Notecard already executed the request.
     DeserError(String<256>),
 
+    /// Response line is not valid JSON, e.g. bytes were lost on the link.
+    InvalidResponse(String<256>),
+
     SerError,
 
     /// Request does not end with '\n'.
@@ -17,6 +41,7 @@ pub enum Error {
 
     TimeOut,
 
+    /// Response did not fit in the driver buffer, the rest of the line was discarded.
     BufOverflow,
 
     /// Method called when notecarrier is in invalid state.
@@ -32,13 +57,173 @@ pub enum Error {
     ErrorAddingNote(String<256>),
 
     NotecardErr(String<256>),
+
+    /// Notecard I/O error (`{io}`), worth retrying.
+    CardIo(String<256>),
+
+    /// Request not supported by the Notecard firmware (`{not-supported}`).
+    NotSupported(String<256>),
+
+    /// Binary data on the Notecard is invalid (`{bad-bin}`).
+    BadBinary(String<256>),
+
+    /// Notefile does not exist (`{file-noexist}`).
+    NotefileNotFound(String<256>),
+
+    /// Note does not exist (`{note-noexist}`).
+    NoteNotFound(String<256>),
+
+    /// The crc echoed on a response does not match its content or sequence number.
+    CrcMismatch,
+
+    /// Request sent to a [`Replay`](crate::replay::Replay) differs from the recorded one, holds the request sent.
+    ReplayMismatch(String<256>),
+
+    /// Transaction still failed after `attempts`, `last` caused the last failure.
+    RetriesExhausted { attempts: usize, last: TransientError },
+}
+
+/// Transient failure that caused [`Error::RetriesExhausted`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransientError {
+    WriteError(embedded_io::ErrorKind),
+    ReadError(embedded_io::ErrorKind),
+    I2cError(embedded_hal::i2c::ErrorKind),
+    InvalidResponse,
+    RemainingData,
+    TimeOut,
+    CrcMismatch,
+    CardIo,
 }
 
 impl Error {
     pub fn new_desererror(msg: &[u8]) -> Error {
-        let msg = core::str::from_utf8(&msg).unwrap_or("[invalid utf8]");
-        let mut s = String::new();
-        s.push_str(msg).ok();
-        Error::DeserError(s)
+        Error::DeserError(truncated(msg))
+    }
+
+    pub(crate) fn new_invalid_response(msg: &[u8]) -> Error {
+        Error::InvalidResponse(truncated(msg))
+    }
+
+    pub(crate) fn new_replay_mismatch(request: &[u8]) -> Error {
+        Error::ReplayMismatch(truncated(request))
+    }
+
+    /// Build an error from the `"err"` field of a Notecard response, if there is one.
+    pub(crate) fn from_notecard_err(result: &[u8]) -> Option<Error> {
+        serde_json_core::from_slice::<ResponseEnvelope>(result)
+            .ok()
+            .and_then(|(res, _)| res.err)
+            .map(Error::from_err_message)
+    }
+
+    /// Map the error message of a Notecard response onto a variant by its `{token}`.
+    ///
+    /// The whole message is searched for the token, the variant keeps it cut off at the capacity.
+    pub(crate) fn from_err_message(message: &str) -> Error {
+        let err = truncated(message.as_bytes());
+        if message.contains("{dfu-in-progress}") {
+            Error::DFUInProgress
+        } else if message.contains("{io}") {
+            Error::CardIo(err)
+        } else if message.contains("{not-supported}") {
+            Error::NotSupported(err)
+        } else if message.contains("{bad-bin}") {
+            Error::BadBinary(err)
+        } else if message.contains("{file-storage-full}") {
+            Error::FileStorageFull(err)
+        } else if message.contains("{file-noexist}") || message.contains("{notefile-noexist}") {
+            Error::NotefileNotFound(err)
+        } else if message.contains("{note-noexist}") {
+            Error::NoteNotFound(err)
+        } else if message.contains("error adding note") {
+            Error::ErrorAddingNote(err)
+        } else {
+            Error::NotecardErr(err)
+        }
+    }
+
+    /// Errors caused by the link to the Notecard that may succeed when the request is resent.
+    ///
+    /// The driver resyncs and retries these on its own, up to `Config::transaction_retry` times.
+    pub fn is_transient(&self) -> bool {
+        self.transient().is_some()
+    }
+
+    /// The kind of a transient error, `None` for errors that are not retried.
+    pub fn transient(&self) -> Option<TransientError> {
+        match self {
+            Error::WriteError(kind) => Some(TransientError::WriteError(*kind)),
+            Error::ReadError(kind) => Some(TransientError::ReadError(*kind)),
+            Error::I2cError(kind) => Some(TransientError::I2cError(*kind)),
+            Error::InvalidResponse(_) => Some(TransientError::InvalidResponse),
+            Error::RemainingData => Some(TransientError::RemainingData),
+            Error::TimeOut => Some(TransientError::TimeOut),
+            Error::CrcMismatch => Some(TransientError::CrcMismatch),
+            Error::CardIo(_) => Some(TransientError::CardIo),
+            _ => None,
+        }
+    }
+}
+
+/// `msg` as a string, cut off at the capacity.
+fn truncated(msg: &[u8]) -> String<256> {
+    let msg = core::str::from_utf8(msg).unwrap_or("[invalid utf8]");
+    let mut s = String::new();
+    for c in msg.chars() {
+        if s.push(c).is_err() {
+            break;
+        }
+    }
+    s
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::WriteError(kind) => write!(f, "write to Notecard failed: {:?}", kind),
+            Error::ReadError(kind) => write!(f, "read from Notecard failed: {:?}", kind),
+            Error::I2cError(kind) => write!(f, "I2C transfer to Notecard failed: {}", kind),
+            Error::DeserError(response) => write!(f, "failed to parse response: {}", response),
+            Error::InvalidResponse(response) => write!(f, "response is not valid JSON: {}", response),
+            Error::SerError => f.write_str("failed to serialize request"),
+            Error::InvalidRequest => f.write_str("invalid request"),
+            Error::RemainingData => f.write_str("unexpected data from Notecard"),
+            Error::TimeOut => f.write_str("Notecard did not respond in time"),
+            Error::BufOverflow => f.write_str("response exceeds the driver buffer"),
+            Error::WrongState => f.write_str("driver in wrong state"),
+            Error::DFUInProgress => f.write_str("Notecard firmware update in progress"),
+            Error::FileStorageFull(err)
+            | Error::ErrorAddingNote(err)
+            | Error::NotecardErr(err)
+            | Error::CardIo(err)
+            | Error::NotSupported(err)
+            | Error::BadBinary(err)
+            | Error::NotefileNotFound(err)
+            | Error::NoteNotFound(err) => write!(f, "Notecard error: {}", err),
+            Error::CrcMismatch => f.write_str("response crc mismatch"),
+            Error::ReplayMismatch(request) => write!(f, "request differs from the recorded one: {}", request),
+            Error::RetriesExhausted { attempts, last } => {
+                write!(f, "transaction failed after {} attempts, last with {}", attempts, last)
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+impl fmt::Display for TransientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransientError::WriteError(kind) => write!(f, "write to Notecard failed: {:?}", kind),
+            TransientError::ReadError(kind) => write!(f, "read from Notecard failed: {:?}", kind),
+            TransientError::I2cError(kind) => write!(f, "I2C transfer to Notecard failed: {}", kind),
+            TransientError::InvalidResponse => f.write_str("response is not valid JSON"),
+            TransientError::RemainingData => f.write_str("unexpected data from Notecard"),
+            TransientError::TimeOut => f.write_str("Notecard did not respond in time"),
+            TransientError::CrcMismatch => f.write_str("response crc mismatch"),
+            TransientError::CardIo => f.write_str("Notecard I/O error"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
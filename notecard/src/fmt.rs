@@ -0,0 +1,59 @@
+//! Logging through `defmt` or `log`, whichever feature is enabled. With both enabled, e.g. when
+//! features are unified across a workspace, `defmt` takes precedence.
+//!
+//! Without either feature the arguments are still evaluated by reference so they do not turn into
+//! unused variables, but nothing is logged.
+
+macro_rules! trace {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::trace!($s $(, $x)*);
+            #[cfg(all(feature = "log", not(feature = "defmt")))]
+            ::log::trace!($s $(, $x)*);
+            #[cfg(not(any(feature = "defmt", feature = "log")))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+macro_rules! debug {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::debug!($s $(, $x)*);
+            #[cfg(all(feature = "log", not(feature = "defmt")))]
+            ::log::debug!($s $(, $x)*);
+            #[cfg(not(any(feature = "defmt", feature = "log")))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+macro_rules! error {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::error!($s $(, $x)*);
+            #[cfg(all(feature = "log", not(feature = "defmt")))]
+            ::log::error!($s $(, $x)*);
+            #[cfg(not(any(feature = "defmt", feature = "log")))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
+
+/// Log a `Debug` value, `defmt` needs it wrapped.
+#[cfg(feature = "defmt")]
+pub(crate) use defmt::Debug2Format;
+
+/// Log a `Debug` value, `defmt` needs it wrapped.
+#[cfg(not(feature = "defmt"))]
+pub(crate) struct Debug2Format<'a, T: core::fmt::Debug + ?Sized>(pub &'a T);
+
+#[cfg(not(feature = "defmt"))]
+impl<T: core::fmt::Debug + ?Sized> core::fmt::Debug for Debug2Format<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
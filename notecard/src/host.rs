@@ -0,0 +1,97 @@
+//! Host support for Linux and other `std` targets, enabled with the `std` feature.
+//!
+//! Notecards attached over a USB-to-serial adapter or their own USB-CDC port show up as a serial
+//! device, e.g. `/dev/ttyACM0`. [`open`] wraps it into a [`Serial`] transport and [`TokioDelay`]
+//! provides the delay, both run on a tokio runtime.
+//!
+//! ```rust, ignore
+//! let mut note = Notecard::new(host::open("/dev/ttyACM0", 9600)?, TokioDelay);
+//! let hub = note.transaction(hub::req::HubGet::default()).await?;
+//! ```
+//!
+//! USB-CDC is flow controlled, [`crate::Config::pacing`] can be turned off for it.
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_io_adapters::tokio_1::FromTokio;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+use crate::transport::Serial;
+
+/// Serial transport on top of a host serial device.
+pub type HostSerial = Serial<FromTokio<SerialStream>>;
+
+/// Open the serial device at `path` with `baud_rate`.
+///
+/// The baud rate is ignored by USB-CDC devices.
+pub fn open(path: &str, baud_rate: u32) -> Result<HostSerial, tokio_serial::Error> {
+    let stream = tokio_serial::new(path, baud_rate).open_native_async()?;
+    Ok(Serial::new(FromTokio::new(stream)))
+}
+
+/// Delay backed by the tokio timer.
+#[derive(Clone, Copy, Default)]
+pub struct TokioDelay;
+
+impl DelayNs for TokioDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        tokio::time::sleep(core::time::Duration::from_nanos(ns as u64)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::time::Duration;
+    use std::vec::Vec;
+
+    use serialport::{SerialPort, TTYPort};
+
+    use super::*;
+    use crate::{hub, Notecard};
+
+    /// Answer as the Notecard on the master side of a pseudo-terminal.
+    fn notecard(mut master: TTYPort) {
+        let mut line = Vec::new();
+        let mut byte = [0_u8; 1];
+        loop {
+            match master.read(&mut byte) {
+                Ok(0) => continue,
+                Ok(_) => (),
+                Err(err) if err.kind() == ErrorKind::TimedOut => continue,
+                // The slave side reports a hangup until the driver opens it.
+                Err(_) => {
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+            }
+            if byte[0] != b'\n' {
+                line.push(byte[0]);
+                continue;
+            }
+
+            let response: &[u8] = match &line[..] {
+                b"" => b"\r\n",
+                br#"{"req":"hub.get"}"# => b"{\"product\":\"com.example:test\"}\r\n",
+                _ => b"{\"err\":\"unknown request\"}\r\n",
+            };
+            line.clear();
+            if master.write_all(response).is_err() {
+                return;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn transaction_over_pty() {
+        // The paired slave holds a lock on the device, so it is closed and reopened through `open`.
+        let (master, slave) = TTYPort::pair().unwrap();
+        let path = slave.name().unwrap();
+        drop(slave);
+        std::thread::spawn(move || notecard(master));
+
+        let mut note = Notecard::new(open(&path, 9600).unwrap(), TokioDelay);
+        let hub = note.transaction(hub::req::HubGet::default()).await.unwrap();
+
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+    }
+}
@@ -5,7 +5,8 @@ pub mod req {
     use super::*;
     use crate::NoteTransaction;
 
-    #[derive(Deserialize, Serialize, defmt::Format)]
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     #[derive(NoteTransaction)]
     #[note_transaction(result_type = res::Hub)]
     pub struct HubGet {
@@ -20,7 +21,8 @@ pub mod req {
         }
     }
 
-    #[derive(Deserialize, Serialize, defmt::Format)]
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     #[serde(rename_all = "lowercase")]
     pub enum HubMode {
         Periodic,
@@ -30,7 +32,8 @@ pub mod req {
         DFU,
     }
 
-    #[derive(Deserialize, Serialize, defmt::Format)]
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub struct HubSet<'a> {
         pub req: &'static str,
 
@@ -90,10 +93,12 @@ pub mod req {
 pub mod res {
     use super::*;
 
-    #[derive(Deserialize, defmt::Format)]
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub struct Empty {}
 
-    #[derive(Deserialize, defmt::Format)]
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub struct Hub {
         pub device: Option<heapless::String<40>>,
         pub product: Option<heapless::String<120>>,
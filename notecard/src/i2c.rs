@@ -0,0 +1,351 @@
+//! Serial-over-I2C transport for the Notecard.
+//!
+//! The Notecard does not expose a register map over I2C. Instead it tunnels the same newline
+//! terminated JSON stream used on the UART through small length prefixed frames:
+//!
+//! - Write: `[len, data...]` with `len` up to `CHUNK_LENGTH_MAX` bytes.
+//! - Read: write `[0, requested]`, then read `requested + 2` bytes `[available, good, data...]`,
+//!   where `available` is the number of bytes still waiting on the Notecard and `good` is the
+//!   number of data bytes returned.
+//!
+//! Requesting `0` bytes is used to query the number of bytes the Notecard has pending.
+
+use chrono::Duration;
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::{Error as _, I2c};
+use embedded_io::ErrorKind;
+
+use crate::error::Error;
+use crate::transport::{paced_chunks, LineReader, NotecardTransport, ResetScanner};
+use crate::{Config, CARD_RESET_DRAIN_DELAY, CHUNK_LENGTH_MAX};
+
+/// Default I2C address of the Notecard.
+pub const NOTECARD_I2C_ADDRESS: u8 = 0x17;
+
+/// Delay between polls while waiting for the Notecard to have data available (ms).
+const POLL_DELAY_MS: u32 = 1;
+
+/// Delay between requesting data and reading it back (ms).
+///
+/// > `note-c` waits briefly after the read request so the Notecard can prepare the response.
+const REQUEST_DELAY_MS: u32 = 1;
+
+/// Transport to the Notecard over I2C.
+pub struct I2cSerial<I2C: I2c> {
+    i2c: I2C,
+    address: u8,
+
+    // Number of bytes the Notecard reported as still pending after the last read.
+    available: usize,
+}
+
+impl<I2C: I2c> I2cSerial<I2C> {
+    /// Create a new serial-over-I2C transport using the default Notecard address.
+    pub fn new(i2c: I2C) -> Self {
+        Self::new_with_address(i2c, NOTECARD_I2C_ADDRESS)
+    }
+
+    /// Create a new serial-over-I2C transport using a custom Notecard address.
+    pub fn new_with_address(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            available: 0,
+        }
+    }
+
+    /// Release the I2C bus.
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// Write `data` in frames of at most `CHUNK_LENGTH_MAX` bytes.
+    async fn transmit(&mut self, data: &[u8]) -> Result<(), Error> {
+        for chunk in data.chunks(CHUNK_LENGTH_MAX) {
+            let mut frame = [0_u8; CHUNK_LENGTH_MAX + 1];
+            frame[0] = chunk.len() as u8;
+            frame[1..=chunk.len()].copy_from_slice(chunk);
+
+            self.i2c
+                .write(self.address, &frame[..=chunk.len()])
+                .await
+                .map_err(|err| Error::I2cError(err.kind()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Request up to `buffer.len()` pending bytes from the Notecard.
+    ///
+    /// Returns the number of data bytes received. An empty `buffer` only refreshes the number of
+    /// available bytes.
+    async fn receive_frame<D: DelayNs>(&mut self, delay: &mut D, buffer: &mut [u8]) -> Result<usize, Error> {
+        let requested = self.available.min(CHUNK_LENGTH_MAX).min(buffer.len());
+
+        self.i2c
+            .write(self.address, &[0, requested as u8])
+            .await
+            .map_err(|err| Error::I2cError(err.kind()))?;
+        delay.delay_ms(REQUEST_DELAY_MS).await;
+
+        let mut frame = [0_u8; CHUNK_LENGTH_MAX + 2];
+        let frame = &mut frame[..requested + 2];
+        self.i2c
+            .read(self.address, frame)
+            .await
+            .map_err(|err| Error::I2cError(err.kind()))?;
+
+        let good = frame[1] as usize;
+        if good != requested {
+            return Err(Error::ReadError(ErrorKind::InvalidData));
+        }
+
+        self.available = frame[0] as usize;
+        buffer[..good].copy_from_slice(&frame[2..][..good]);
+
+        trace!("nc: i2c: got {} remaining {}", good, self.available);
+
+        Ok(good)
+    }
+
+    /// Wait for the Notecard to have data pending and read the next frame.
+    ///
+    /// Time spent in delays is taken from `budget_ms`, returns `None` once it is used up.
+    async fn poll_frame<D: DelayNs>(
+        &mut self,
+        delay: &mut D,
+        buffer: &mut [u8],
+        budget_ms: &mut u32,
+    ) -> Result<Option<usize>, Error> {
+        while self.available == 0 {
+            self.receive_frame(delay, &mut []).await?;
+            // Every frame waits `REQUEST_DELAY_MS` between the request and the read
+            *budget_ms = budget_ms.saturating_sub(REQUEST_DELAY_MS);
+            if self.available == 0 {
+                if *budget_ms == 0 {
+                    return Ok(None);
+                }
+                delay.delay_ms(POLL_DELAY_MS).await;
+                *budget_ms = budget_ms.saturating_sub(POLL_DELAY_MS);
+            }
+        }
+
+        let len = self.receive_frame(delay, buffer).await?;
+        *budget_ms = budget_ms.saturating_sub(REQUEST_DELAY_MS);
+        Ok(Some(len))
+    }
+}
+
+impl<I2C: I2c> NotecardTransport for I2cSerial<I2C> {
+    async fn send<D: DelayNs>(&mut self, delay: &mut D, request: &[u8], config: &Config) -> Result<(), Error> {
+        for (pause, chunk) in paced_chunks(request, config) {
+            if pause > 0 {
+                delay.delay_ms(pause).await;
+            }
+            self.transmit(chunk).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn receive<D: DelayNs>(&mut self, delay: &mut D, line: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let mut reader = LineReader::new(line);
+        let mut frame = [0_u8; CHUNK_LENGTH_MAX];
+        let mut budget_ms = timeout.num_milliseconds() as u32;
+        loop {
+            let Some(len) = self.poll_frame(delay, &mut frame, &mut budget_ms).await? else {
+                return Err(Error::TimeOut);
+            };
+            if let Some(result) = reader.push(&frame[..len]) {
+                return result;
+            }
+        }
+    }
+
+    async fn resync<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), Error> {
+        self.transmit(b"\n").await?;
+
+        let mut scanner = ResetScanner::default();
+        let mut frame = [0_u8; CHUNK_LENGTH_MAX];
+        loop {
+            let mut budget_ms = CARD_RESET_DRAIN_DELAY.num_milliseconds() as u32;
+            let Some(len) = self.poll_frame(delay, &mut frame, &mut budget_ms).await? else {
+                trace!("nc: Timeout");
+                return Err(Error::TimeOut);
+            };
+
+            for byte in &frame[..len] {
+                if let Some(result) = scanner.push(*byte) {
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::VecDeque;
+    use std::vec::Vec;
+
+    use embedded_hal_async::i2c::{ErrorKind as I2cErrorKind, ErrorType, Operation};
+
+    use super::*;
+    use crate::mock::MockDelay;
+    use crate::blocking::block_on;
+
+    enum Step {
+        Write(Vec<u8>),
+        Read(Vec<u8>),
+    }
+
+    /// Scripted I2C bus, every transfer has to match the next step.
+    #[derive(Default)]
+    pub(crate) struct MockI2c {
+        script: VecDeque<Step>,
+    }
+
+    impl MockI2c {
+        pub(crate) fn write(mut self, data: &[u8]) -> Self {
+            self.script.push_back(Step::Write(data.into()));
+            self
+        }
+
+        pub(crate) fn read(mut self, data: &[u8]) -> Self {
+            self.script.push_back(Step::Read(data.into()));
+            self
+        }
+
+        /// A poll of the pending byte count, answered with `available`.
+        pub(crate) fn query(self, available: u8) -> Self {
+            self.write(&[0, 0]).read(&[available, 0])
+        }
+
+        /// A frame of `data`, with `available` bytes left on the Notecard.
+        pub(crate) fn frame(self, data: &[u8], available: u8) -> Self {
+            let mut frame = std::vec![available, data.len() as u8];
+            frame.extend_from_slice(data);
+            self.write(&[0, data.len() as u8]).read(&frame)
+        }
+
+        #[track_caller]
+        pub(crate) fn assert_done(&self) {
+            assert!(self.script.is_empty(), "i2c: {} transfers left", self.script.len());
+        }
+    }
+
+    impl ErrorType for MockI2c {
+        type Error = I2cErrorKind;
+    }
+
+    impl MockI2c {
+        #[track_caller]
+        fn transfer(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), I2cErrorKind> {
+            assert_eq!(address, NOTECARD_I2C_ADDRESS);
+            for operation in operations {
+                match (operation, self.script.pop_front()) {
+                    (Operation::Write(data), Some(Step::Write(expected))) => assert_eq!(*data, &expected[..]),
+                    (Operation::Read(buffer), Some(Step::Read(data))) => {
+                        assert_eq!(buffer.len(), data.len(), "i2c: unexpected read length");
+                        buffer.copy_from_slice(&data);
+                    }
+                    _ => panic!("i2c: unexpected transfer"),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl I2c for MockI2c {
+        async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            self.transfer(address, operations)
+        }
+    }
+
+    impl embedded_hal::i2c::I2c for MockI2c {
+        fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            self.transfer(address, operations)
+        }
+    }
+
+    #[test]
+    fn transmit_in_frames() {
+        let request = [b'x'; CHUNK_LENGTH_MAX + 3];
+        let mut first = std::vec![CHUNK_LENGTH_MAX as u8];
+        first.extend_from_slice(&request[..CHUNK_LENGTH_MAX]);
+        let i2c = MockI2c::default().write(&first).write(&[3, b'x', b'x', b'x']);
+
+        let config = Config {
+            pacing: false,
+            ..Default::default()
+        };
+        let mut serial = I2cSerial::new(i2c);
+        block_on(serial.send(&mut MockDelay::new(), &request, &config)).unwrap();
+        serial.release().assert_done();
+    }
+
+    #[test]
+    fn receive_in_frames() {
+        let i2c = MockI2c::default()
+            .query(0)
+            .query(4)
+            .frame(br#"{"to"#, 6)
+            .frame(b"tal\":1", 3)
+            .frame(b"}\r\n", 0);
+
+        let mut serial = I2cSerial::new(i2c);
+        let mut line = [0_u8; 32];
+        let len = block_on(serial.receive(&mut MockDelay::new(), &mut line, Duration::seconds(1))).unwrap();
+
+        assert_eq!(&line[..len], b"{\"total\":1}\r\n");
+        serial.release().assert_done();
+    }
+
+    #[test]
+    fn receive_timeout() {
+        // Every poll takes the request delay and the poll delay from the budget
+        let timeout_ms = 10;
+        let polls = timeout_ms / (REQUEST_DELAY_MS + POLL_DELAY_MS) + 1;
+        let i2c = (0..polls).fold(MockI2c::default(), |i2c, _| i2c.query(0));
+
+        let delay = MockDelay::new();
+        let mut serial = I2cSerial::new(i2c);
+        let mut line = [0_u8; 32];
+        let result = block_on(serial.receive(&mut delay.clone(), &mut line, Duration::milliseconds(timeout_ms as i64)));
+
+        assert!(matches!(result, Err(Error::TimeOut)));
+        assert!(delay.elapsed() <= Duration::milliseconds((timeout_ms + REQUEST_DELAY_MS) as i64));
+        serial.release().assert_done();
+    }
+
+    #[test]
+    fn short_frame() {
+        let i2c = MockI2c::default()
+            .query(4)
+            .write(&[0, 4])
+            .read(&[0, 2, b'{', b'}', 0, 0]);
+
+        let mut serial = I2cSerial::new(i2c);
+        let mut line = [0_u8; 32];
+        let result = block_on(serial.receive(&mut MockDelay::new(), &mut line, Duration::seconds(1)));
+
+        assert!(matches!(result, Err(Error::ReadError(ErrorKind::InvalidData))));
+        serial.release().assert_done();
+    }
+
+    #[test]
+    fn resync() {
+        let i2c = MockI2c::default().write(&[1, b'\n']).query(0).query(2).frame(b"\r\n", 0);
+
+        let mut serial = I2cSerial::new(i2c);
+        block_on(serial.resync(&mut MockDelay::new())).unwrap();
+        serial.release().assert_done();
+
+        let i2c = MockI2c::default().write(&[1, b'\n']).query(3).frame(b"x\r\n", 0);
+
+        let mut serial = I2cSerial::new(i2c);
+        let result = block_on(serial.resync(&mut MockDelay::new()));
+        assert!(matches!(result, Err(Error::RemainingData)));
+        serial.release().assert_done();
+    }
+}
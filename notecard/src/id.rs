@@ -0,0 +1,45 @@
+//! Request correlation through the `"id"` field.
+//!
+//! The Notecard echoes an integer `"id"` from a request on its response. Tagging each request with
+//! its sequence number tells a response to the current request apart from a late response to an
+//! earlier one that timed out.
+
+use core::fmt::Write;
+
+use heapless::String;
+use serde::Deserialize;
+
+use crate::buffer::Buffer;
+use crate::error::Error;
+
+/// Length of `,"id":65535}`.
+const ID_FIELD_LENGTH: usize = 12;
+
+#[derive(Deserialize)]
+struct IdResponse {
+    id: Option<u32>,
+}
+
+/// Append the id field to the JSON object in `buffer`.
+///
+/// `buffer` must hold exactly one serialized JSON object without the terminating newline.
+pub(crate) fn add_id<S: AsRef<[u8]> + AsMut<[u8]>>(buffer: &mut Buffer<S>, id: u16) -> Result<(), Error> {
+    if buffer.len() < 2 || buffer.last() != Some(&b'}') {
+        return Err(Error::InvalidRequest);
+    }
+
+    let separator = if buffer[buffer.len() - 2] == b'{' { "" } else { "," };
+    let mut field: String<ID_FIELD_LENGTH> = String::new();
+    write!(field, "{}\"id\":{}}}", separator, id).map_err(|_| Error::SerError)?;
+
+    // Replace the closing brace with the field
+    buffer.pop();
+    buffer.extend_from_slice(field.as_bytes()).map_err(|_| Error::SerError)
+}
+
+/// The id echoed on a response, if any.
+pub(crate) fn response_id(response: &[u8]) -> Option<u32> {
+    serde_json_core::from_slice::<IdResponse>(response)
+        .ok()
+        .and_then(|(res, _)| res.id)
+}
@@ -0,0 +1,155 @@
+//! Walking JSON objects in place.
+//!
+//! Responses like `note.changes` hold an object keyed by note id, which cannot be deserialized
+//! into a fixed size type. The scanner splits an object into its raw members instead, so they can
+//! be deserialized one at a time straight from the response buffer.
+//!
+//! The driver also uses it to tell a response garbled on the link from one that is valid JSON but
+//! does not match the expected type.
+
+/// Iterator over the members of a JSON object, yields the raw key and value.
+///
+/// Keys are returned as they appear between the quotes, escapes are not resolved.
+pub(crate) struct Members<'a> {
+    data: &'a [u8],
+    pos: usize,
+    done: bool,
+}
+
+/// Iterate over the members of the JSON object in `data`.
+pub(crate) fn members(data: &[u8]) -> Option<Members<'_>> {
+    let pos = skip_whitespace(data, 0);
+    if data.get(pos) != Some(&b'{') {
+        return None;
+    }
+
+    let pos = skip_whitespace(data, pos + 1);
+    Some(Members {
+        data,
+        pos,
+        done: data.get(pos) == Some(&b'}'),
+    })
+}
+
+fn skip_whitespace(data: &[u8], mut pos: usize) -> usize {
+    while data.get(pos).is_some_and(u8::is_ascii_whitespace) {
+        pos += 1;
+    }
+    pos
+}
+
+/// End of the string starting at `pos`, after its closing quote.
+fn skip_string(data: &[u8], mut pos: usize) -> Option<usize> {
+    pos += 1;
+    loop {
+        match data.get(pos)? {
+            b'\\' => pos += 2,
+            b'"' => return Some(pos + 1),
+            _ => pos += 1,
+        }
+    }
+}
+
+/// Nesting depth up to which values are walked, deeper values are rejected.
+const MAX_DEPTH: usize = 32;
+
+/// End of the value starting at `pos`, `None` if it is not valid JSON.
+fn skip_value(data: &[u8], pos: usize) -> Option<usize> {
+    skip_nested(data, pos, MAX_DEPTH)
+}
+
+fn skip_nested(data: &[u8], pos: usize, depth: usize) -> Option<usize> {
+    match data.get(pos)? {
+        b'"' => skip_string(data, pos),
+        b'{' => skip_container(data, pos, b'}', depth.checked_sub(1)?),
+        b'[' => skip_container(data, pos, b']', depth.checked_sub(1)?),
+        _ => skip_scalar(data, pos),
+    }
+}
+
+/// End of the object or array starting at `pos`, objects are closed by `}`.
+fn skip_container(data: &[u8], pos: usize, close: u8, depth: usize) -> Option<usize> {
+    let mut pos = skip_whitespace(data, pos + 1);
+    if data.get(pos) == Some(&close) {
+        return Some(pos + 1);
+    }
+
+    loop {
+        if close == b'}' {
+            if data.get(pos) != Some(&b'"') {
+                return None;
+            }
+            pos = skip_whitespace(data, skip_string(data, pos)?);
+            if data.get(pos) != Some(&b':') {
+                return None;
+            }
+            pos = skip_whitespace(data, pos + 1);
+        }
+
+        pos = skip_whitespace(data, skip_nested(data, pos, depth)?);
+        match data.get(pos)? {
+            b',' => pos = skip_whitespace(data, pos + 1),
+            byte if *byte == close => return Some(pos + 1),
+            _ => return None,
+        }
+    }
+}
+
+/// End of the number, `true`, `false` or `null` starting at `pos`.
+fn skip_scalar(data: &[u8], pos: usize) -> Option<usize> {
+    let len = data[pos..]
+        .iter()
+        .take_while(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'+' | b'-' | b'.'))
+        .count();
+    (len > 0).then_some(pos + len)
+}
+
+/// Whether `data` holds a single JSON object, surrounded by whitespace at most.
+pub(crate) fn is_object(data: &[u8]) -> bool {
+    let pos = skip_whitespace(data, 0);
+    data.get(pos) == Some(&b'{')
+        && skip_value(data, pos).is_some_and(|end| skip_whitespace(data, end) == data.len())
+}
+
+impl<'a> Members<'a> {
+    fn member(&mut self) -> Option<(&'a str, &'a [u8])> {
+        let data = self.data;
+
+        if data.get(self.pos) != Some(&b'"') {
+            return None;
+        }
+        let key_end = skip_string(data, self.pos)?;
+        let key = core::str::from_utf8(&data[self.pos + 1..key_end - 1]).ok()?;
+
+        let pos = skip_whitespace(data, key_end);
+        if data.get(pos) != Some(&b':') {
+            return None;
+        }
+        let value_start = skip_whitespace(data, pos + 1);
+        let value_end = skip_value(data, value_start)?;
+
+        let pos = skip_whitespace(data, value_end);
+        match data.get(pos)? {
+            b',' => self.pos = skip_whitespace(data, pos + 1),
+            b'}' => self.done = true,
+            _ => return None,
+        }
+
+        Some((key, &data[value_start..value_end]))
+    }
+}
+
+impl<'a> Iterator for Members<'a> {
+    type Item = Result<(&'a str, &'a [u8]), ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let member = self.member().ok_or(());
+        // Stop after malformed input
+        self.done |= member.is_err();
+        Some(member)
+    }
+}
@@ -4,6 +4,19 @@
 //!
 //! The driver is designed to work over the UART serial 9600 baud, the AUX UART serial 115200 baud and the i2c interface.
 //!
+//! The link to the Notecard is a [`transport::NotecardTransport`]. Wrap an `embedded_io_async`
+//! UART in [`transport::Serial`] and an i2c bus in [`i2c::I2cSerial`], which implements the
+//! Notecard serial-over-I2C framing.
+//!
+//! A blocking driver for firmware without an async executor is available in [`blocking`].
+//!
+//! With the `std` feature, `host` connects to Notecards attached to a Linux machine over serial
+//! or USB-CDC. The `mock` feature adds `mock`, a scripted Notecard for host unit tests.
+//!
+//! Traffic of any transport can be captured and played back with [`replay`].
+//!
+//! The driver logs through `defmt` or `log`, enabled with the feature of the same name.
+//!
 //! # Example
 //!
 //! ```rust, ignore
@@ -16,21 +29,45 @@
 //! ```
 
 #![no_std]
+// Errors carry heapless strings with the Notecard's message by design.
+#![allow(clippy::result_large_err)]
+
+#[cfg(any(test, feature = "mock"))]
+extern crate std;
 
 use core::default::Default;
+use core::future::poll_fn;
+use core::task::Poll;
 
 use chrono::Duration;
 
-use defmt::{debug, error, trace};
 use embedded_hal_async::delay::DelayNs;
-use embedded_io_async::{Read, Write};
-use futures::{select_biased, FutureExt};
-use heapless::Vec;
 use serde::{de::DeserializeOwned, Serialize};
-pub use notecard_next_macro::NoteTransaction;
 
+use buffer::Buffer;
+use transport::NotecardTransport;
+pub use notecard_next_macro::{NoteCommand, NoteTransaction};
+
+#[macro_use]
+mod fmt;
+
+pub mod blocking;
+mod buffer;
+pub mod card;
+mod crc;
 mod error;
+pub use error::{Error, TransientError};
+#[cfg(feature = "std")]
+pub mod host;
 pub mod hub;
+pub mod i2c;
+mod id;
+mod json;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod note;
+pub mod replay;
+pub mod transport;
 
 const CARD_RESET_DRAIN_DELAY: Duration = Duration::milliseconds(500);
 const DEFAULT_BUF_SIZE: usize = 18 * 1024;
@@ -46,11 +83,18 @@ const CHUNK_LENGTH: usize = if CHUNK_LENGTH_I < CHUNK_LENGTH_MAX {
 // divisible to avoid unnecessary fragmentation.
 const SEGMENT_LENGTH: usize = (250 / CHUNK_LENGTH) * CHUNK_LENGTH;
 
+// Firmware from which the AUX UART keeps up with unpaced requests, see the note on
+// `Config::segment_delay`. No reduced set of delays is documented, pacing is turned off instead.
+const FAST_PACING_FIRMWARE: FirmwareVersion = FirmwareVersion::new(3, 4, 0, 0);
+
 pub struct Config {
     /// Response timeout in (ms)
     pub response_timeout: Duration,
 
     /// Transaction retry count
+    ///
+    /// Number of times a transaction is resent after a transient failure, also bounds the number
+    /// of attempts to resync the interface.
     pub transaction_retry: usize,
 
     /// Delay between chunks when transmitting (ms).
@@ -69,6 +113,43 @@ pub struct Config {
     /// > `note-c`: https://github.com/blues/note-c/blob/master/n_lib.h#L46
     /// > Original: 250 ms.
     pub segment_delay: Duration,
+
+    /// Split requests into chunks and segments and pace them with `chunk_delay` and
+    /// `segment_delay`.
+    ///
+    /// Required for the primary UART at 9600 baud and for i2c. On the AUX UART with firmware
+    /// version 3.4 and above, see the note on `segment_delay`, disabling pacing saves hundreds of
+    /// ms per request.
+    pub pacing: bool,
+
+    /// Maximum number of bytes sent before pausing for `segment_delay`.
+    ///
+    /// > `note-c` uses 250 bytes, the default is rounded down to a multiple of the chunk length.
+    pub segment_length: usize,
+
+    /// Query `card.version` on the first reset and turn pacing off if the detected firmware and
+    /// the transport allow it.
+    ///
+    /// The detected version is available from `Notecard::firmware_version`. Detection failures
+    /// are logged and leave it unset, detection is not attempted again.
+    ///
+    /// On transports that allow it, see `NotecardTransport::fast_pacing`, firmware version 3.4 and
+    /// above turns `pacing` off. Pacing changed from its defaults is kept as configured.
+    pub detect_firmware: bool,
+
+    /// Append a `"crc"` field with sequence number to requests and validate the one echoed on
+    /// responses.
+    ///
+    /// Corrupted transactions fail with `Error::CrcMismatch` and are retried.
+    ///
+    /// > `note-c`: https://github.com/blues/note-c/blob/master/n_request.c
+    pub crc: bool,
+
+    /// Tag requests with an `"id"` field and check the one echoed on responses.
+    ///
+    /// A response with a different id was left over from an earlier request that timed out, it is
+    /// discarded and the next response is read instead.
+    pub request_id: bool,
 }
 
 impl Default for Config {
@@ -78,6 +159,11 @@ impl Default for Config {
             transaction_retry: 5,
             chunk_delay: Duration::milliseconds(20),
             segment_delay: Duration::milliseconds(250),
+            pacing: true,
+            segment_length: SEGMENT_LENGTH,
+            detect_firmware: false,
+            crc: false,
+            request_id: false,
         }
     }
 }
@@ -85,14 +171,56 @@ impl Default for Config {
 pub struct SuspendState {
     config: Config,
     reset_required: bool,
+    sequence_number: u16,
+    firmware_version: Option<FirmwareVersion>,
+    firmware_detected: bool,
+}
+
+impl SuspendState {
+    fn new(config: Config) -> Self {
+        Self {
+            config,
+            reset_required: true,
+            sequence_number: 0,
+            firmware_version: None,
+            firmware_detected: false,
+        }
+    }
+}
+
+/// Notecard firmware version as reported by `card.version`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub build: u32,
+}
+
+impl FirmwareVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32, build: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+            build,
+        }
+    }
 }
 
+/// Notecard driver.
+///
+/// The request and response buffer holds `BUF_SIZE` bytes inline, 18 KiB with `new`. Pick
+/// another size with `new_sized`, or use [`StaticNotecard`] to place the buffer in a caller
+/// supplied `&'static mut [u8]` instead.
 pub struct Notecard<
-    IFT: Read + Write,
+    IFT: NotecardTransport,
     D: DelayNs,
     const BUF_SIZE: usize = DEFAULT_BUF_SIZE,
+    S: AsRef<[u8]> + AsMut<[u8]> = [u8; BUF_SIZE],
 > {
-    interface: IFT,
+    transport: IFT,
     delay: D,
 
     // Configuration
@@ -100,96 +228,310 @@ pub struct Notecard<
 
     // State
     reset_required: bool,
+    sequence_number: u16,
+    firmware_version: Option<FirmwareVersion>,
+    // Set once detection ran, successful or not
+    firmware_detected: bool,
 
-    buffer: Vec<u8, BUF_SIZE>,
+    buffer: Buffer<S>,
 }
 
-enum ResetResult {
-    NonCRResult,
-    CRResult,
-}
+/// Notecard driver using a borrowed buffer, e.g. to place it in a specific RAM region.
+pub type StaticNotecard<IFT, D> = Notecard<IFT, D, 0, &'static mut [u8]>;
 
-impl<IFT:Read + Write, D: DelayNs> Notecard<IFT, D> {
+impl<IFT: NotecardTransport, D: DelayNs> Notecard<IFT, D> {
     /// Create a new Notecard driver handler with the default configuration
-    pub fn new(interface: IFT, delay: D) -> Self {
-        Self::new_with_config(interface, delay, Config::default())
+    pub fn new(transport: IFT, delay: D) -> Self {
+        Self::new_sized(transport, delay)
     }
 
     /// Create a new Notecard driver with a custom configuration
-    pub fn new_with_config(interface: IFT, delay: D, config: Config) -> Self {
-        Self {
-            interface,
+    pub fn new_with_config(transport: IFT, delay: D, config: Config) -> Self {
+        Self::new_sized_with_config(transport, delay, config)
+    }
+
+    /// Recreate the driver from an existing state and a transport.
+    pub fn resume(transport: IFT, delay: D, state: SuspendState) -> Self {
+        Self::resume_sized(transport, delay, state)
+    }
+}
+
+impl<IFT: NotecardTransport, D: DelayNs, const BUF_SIZE: usize> Notecard<IFT, D, BUF_SIZE> {
+    /// Create a new Notecard driver with a `BUF_SIZE` bytes buffer, e.g.
+    /// `Notecard::<_, _, 4096>::new_sized(transport, delay)`
+    pub fn new_sized(transport: IFT, delay: D) -> Self {
+        Self::new_sized_with_config(transport, delay, Config::default())
+    }
+
+    /// Create a new Notecard driver with a `BUF_SIZE` bytes buffer and a custom configuration
+    pub fn new_sized_with_config(transport: IFT, delay: D, config: Config) -> Self {
+        Self::from_parts(transport, delay, SuspendState::new(config), [0; BUF_SIZE])
+    }
+
+    /// Release the transport by returning it and driver state and consuming the driver.
+    pub fn suspend(self) -> (IFT, SuspendState) {
+        let (transport, state, _) = self.into_parts();
+        (transport, state)
+    }
+
+    /// Recreate the driver with a `BUF_SIZE` bytes buffer from an existing state and a transport.
+    pub fn resume_sized(transport: IFT, delay: D, state: SuspendState) -> Self {
+        Self::from_parts(transport, delay, state, [0; BUF_SIZE])
+    }
+}
+
+impl<IFT: NotecardTransport, D: DelayNs> StaticNotecard<IFT, D> {
+    /// Create a new Notecard driver using `buffer` for requests and responses
+    pub fn new_with_buffer(transport: IFT, delay: D, buffer: &'static mut [u8]) -> Self {
+        Self::new_with_buffer_and_config(transport, delay, buffer, Config::default())
+    }
+
+    /// Create a new Notecard driver using `buffer` for requests and responses with a custom
+    /// configuration
+    pub fn new_with_buffer_and_config(
+        transport: IFT,
+        delay: D,
+        buffer: &'static mut [u8],
+        config: Config,
+    ) -> Self {
+        Self::from_parts(transport, delay, SuspendState::new(config), buffer)
+    }
+
+    /// Release the transport and buffer by returning them with the driver state and consuming
+    /// the driver.
+    pub fn suspend(self) -> (IFT, SuspendState, &'static mut [u8]) {
+        self.into_parts()
+    }
+
+    /// Recreate the driver from an existing state, a transport and a buffer.
+    pub fn resume_with_buffer(
+        transport: IFT,
+        delay: D,
+        state: SuspendState,
+        buffer: &'static mut [u8],
+    ) -> Self {
+        Self::from_parts(transport, delay, state, buffer)
+    }
+}
+
+impl<IFT: NotecardTransport, D: DelayNs, const BUF_SIZE: usize, S: AsRef<[u8]> + AsMut<[u8]>>
+    Notecard<IFT, D, BUF_SIZE, S>
+{
+    fn from_parts(transport: IFT, delay: D, state: SuspendState, storage: S) -> Self {
+        Notecard {
+            transport,
             delay,
-            config,
-            reset_required: true,
-            buffer: Vec::new(),
+            config: state.config,
+            reset_required: state.reset_required,
+            sequence_number: state.sequence_number,
+            firmware_version: state.firmware_version,
+            firmware_detected: state.firmware_detected,
+            buffer: Buffer::new(storage),
         }
     }
 
-    /// Release the IFT device by returning the interface and driver state and consuming the driver.
-    pub fn suspend(self) -> (IFT, SuspendState) {
+    fn into_parts(self) -> (IFT, SuspendState, S) {
         (
-            self.interface,
+            self.transport,
             SuspendState {
                 config: self.config,
                 reset_required: self.reset_required,
+                sequence_number: self.sequence_number,
+                firmware_version: self.firmware_version,
+                firmware_detected: self.firmware_detected,
             },
+            self.buffer.into_storage(),
         )
     }
 
-    /// Recreate the driver from an existing state and an interface.
-    pub fn resume(interface: IFT, delay: D, state: SuspendState) -> Self {
-        Notecard {
-            interface,
-            delay,
-            config: state.config,
-            reset_required: state.reset_required,
-            buffer: Vec::new(),
+    /// Execute a json transaction
+    ///
+    /// Transient failures (interface errors, timeouts, garbled responses) resync the interface and
+    /// resend the request up to `Config::transaction_retry` times. Errors reported by the Notecard
+    /// itself and responses that do not match the result type are returned right away.
+    pub async fn transaction<T: Serialize + NoteTransaction>(&mut self, cmd: T) -> Result<<T as NoteTransaction>::NoteResult, error::Error> {
+        self.transact(|buffer| serialize_request(&cmd, buffer), |response| cmd.parse(response))
+            .await
+    }
+
+    /// Send a command, a request the Notecard does not respond to
+    ///
+    /// The request is sent with `"cmd"` in place of its leading `"req"` key, e.g. for
+    /// `card.restart` or high rate `note.add`. Only failures to send it are reported, they are not
+    /// retried.
+    pub async fn command<T: NoteCommand>(&mut self, cmd: T) -> Result<(), error::Error> {
+        let seqno = self.next_sequence_number();
+
+        if self.reset_required {
+            self.reset().await?;
+            debug!("Reset Success!");
         }
+
+        self.buffer.clear();
+
+        let size = serialize_request(&cmd, self.buffer.spare_capacity_mut())?;
+        request_to_command(&mut self.buffer.spare_capacity_mut()[..size])?;
+        self.buffer.advance(size);
+
+        if self.config.crc {
+            crc::add_crc(&mut self.buffer, seqno)?;
+        }
+
+        self.buffer.push(b'\n').map_err(|_| error::Error::SerError)?;
+
+        self.send_request().await.inspect_err(|err| {
+            if err.is_transient() {
+                self.reset_required = true;
+            }
+        })
     }
 
-    /// Execute a json transaction
-    pub async fn transaction<T: Serialize + NoteTransaction>(&mut self, cmd: T) -> Result<<T as NoteTransaction>::NoteResult, error::Error> {
+    /// Execute a json transaction whose result borrows from the response
+    ///
+    /// For responses that are walked in place rather than deserialized at once, e.g.
+    /// `note.changes`. The result borrows the driver buffer until it is dropped.
+    pub async fn transaction_ref<T: Serialize + NoteTransactionRef>(&mut self, cmd: T) -> Result<T::NoteResult<'_>, error::Error> {
+        self.transact(|buffer| serialize_request(&cmd, buffer), |response| cmd.parse(response).map(|_| ()))
+            .await?;
+        cmd.parse(&self.buffer)
+    }
+
+    /// Execute a transaction from a raw JSON request and return the response line
+    ///
+    /// For requests the driver has no types for yet. `req` is a single JSON object, a trailing
+    /// newline is optional. The response is returned without its `\r\n` and is only checked for
+    /// an `"err"` field.
+    pub async fn transaction_raw(&mut self, req: &str) -> Result<&[u8], error::Error> {
+        self.transact(|buffer| copy_request(req, buffer), |_| Ok(())).await?;
+        Ok(self.buffer.trim_ascii_end())
+    }
+
+    /// Execute a transaction from a raw JSON request and return the response line as a string
+    ///
+    /// See [`Notecard::transaction_raw`].
+    pub async fn transaction_raw_str(&mut self, req: &str) -> Result<&str, error::Error> {
+        let response = self.transaction_raw(req).await?;
+        core::str::from_utf8(response).map_err(|_| error::Error::new_desererror(response))
+    }
+
+    /// Run a transaction, retrying transient failures
+    ///
+    /// `encode` writes the request JSON into the buffer and returns its length, `parse` turns the
+    /// response into the result.
+    async fn transact<R>(
+        &mut self,
+        encode: impl Fn(&mut [u8]) -> Result<usize, error::Error>,
+        parse: impl Fn(&[u8]) -> Result<R, error::Error>,
+    ) -> Result<R, error::Error> {
+        // Retries reuse the sequence number of the original request
+        let seqno = self.next_sequence_number();
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let err = match self.try_transaction(&encode, &parse, seqno).await {
+                Ok(result) => return Ok(result),
+                Err(err) => err,
+            };
+            let Some(last) = err.transient() else {
+                return Err(err);
+            };
+
+            // Resync before resending, there may be a partial response in flight
+            self.reset_required = true;
+
+            if attempts > self.config.transaction_retry {
+                error!("nc: transaction failed after {} attempts: {}", attempts, err);
+                return Err(error::Error::RetriesExhausted { attempts, last });
+            }
+
+            debug!("nc: transaction attempt {} failed with {}, retrying", attempts, err);
+        }
+    }
+
+    /// Execute a single attempt of a json transaction
+    async fn try_transaction<R>(
+        &mut self,
+        encode: &impl Fn(&mut [u8]) -> Result<usize, error::Error>,
+        parse: &impl Fn(&[u8]) -> Result<R, error::Error>,
+        seqno: u16,
+    ) -> Result<R, error::Error> {
         if self.reset_required {
             self.reset().await?;
             debug!("Reset Success!");
         }
 
+        self.exchange(encode, parse, seqno).await
+    }
+
+    /// Send a request and parse its response without resyncing the interface first
+    async fn exchange<R>(
+        &mut self,
+        encode: &impl Fn(&mut [u8]) -> Result<usize, error::Error>,
+        parse: &impl Fn(&[u8]) -> Result<R, error::Error>,
+        seqno: u16,
+    ) -> Result<R, error::Error> {
         // Reset JSON buffer
         self.buffer.clear();
-        self.buffer.resize(self.buffer.capacity(), 0).unwrap();
 
         // Serialize the command
-        let size = serde_json_core::to_slice(&cmd, &mut self.buffer).map_err(|_| error::Error::SerError)?;
-        self.buffer.truncate(size);
+        let size = encode(self.buffer.spare_capacity_mut())?;
+        self.buffer.advance(size);
+
+        if self.config.request_id {
+            id::add_id(&mut self.buffer, seqno)?;
+        }
+
+        if self.config.crc {
+            crc::add_crc(&mut self.buffer, seqno)?;
+        }
 
         // Add newline at the end of the JSON to indicate end of command to the notecard
         self.buffer.push(b'\n').map_err(|_| error::Error::SerError)?;
 
-        // TODO: we need timout for the result read and a retry on the request
         self.send_request().await?;
         debug!("nc: Request sent...");
 
         self.read_result().await?;
         debug!("nc: received {:?}", core::str::from_utf8(&self.buffer).ok());
 
-        Ok(cmd.parse(&self.buffer.as_slice())?)
+        if self.config.request_id {
+            while let Some(id) = id::response_id(&self.buffer).filter(|id| *id != seqno as u32) {
+                debug!("nc: discarding stale response with id {}", id);
+                self.read_result().await?;
+            }
+        }
+
+        if self.config.crc {
+            crc::check_crc(&self.buffer, seqno)?;
+        }
+
+        // A line that is not JSON was garbled on the way and is worth resending. A valid response
+        // that fails to parse below means the request was executed, resending it would repeat it.
+        if !json::is_object(&self.buffer) {
+            return Err(error::Error::new_invalid_response(&self.buffer));
+        }
+
+        // Any response may be an error, check for it before parsing the expected result
+        if let Some(err) = error::Error::from_notecard_err(&self.buffer) {
+            return Err(err);
+        }
+
+        parse(&self.buffer)
     }
 
     /// Reset the Notecard
     pub async fn reset(&mut self) -> Result<(), error::Error> {
         debug!("Resetting communication interface");
 
-        for _ in 0..self.config.transaction_retry {
-            match self.try_reset().await {
-                Ok(result) => match result {
-                    ResetResult::NonCRResult => {
-                        debug!("Found unexpected return characters. Retrying sync.")
-                    }
-                    ResetResult::CRResult => return Ok(()),
-                },
+        for _ in 0..self.config.transaction_retry.max(1) {
+            match self.transport.resync(&mut self.delay).await {
+                Ok(()) => {
+                    self.reset_required = false;
+                    return self.detect_firmware().await;
+                }
                 Err(err) => match err {
-                    error::Error::TimeOut => (),
+                    error::Error::TimeOut | error::Error::RemainingData => (),
                     err => return Err(err),
                 },
             }
@@ -198,67 +540,61 @@ impl<IFT:Read + Write, D: DelayNs> Notecard<IFT, D> {
         Err(error::Error::TimeOut)
     }
 
-    /// Attempt to reset the Notecard interface
-    async fn try_reset(&mut self) -> Result<ResetResult, error::Error> {
-        if let Err(e) = self.interface.write_all(b"\n").await {
-            error!(
-                "nc: Sending reset newline failed with {}",
-                defmt::Debug2Format(&e)
-            );
-            self.delay
-                .delay_ms(CARD_RESET_DRAIN_DELAY.num_milliseconds() as u32)
-                .await;
-        }
+    fn next_sequence_number(&mut self) -> u16 {
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.sequence_number
+    }
 
-        let mut carrige_return_found = false;
-        let mut newline_found = false;
-        let mut not_control_char_found = false;
-        loop {
-            let mut buffer = [0_u8; 1];
-            let read = select_biased! {
-                char = self.interface.read(&mut buffer).fuse() => {
-                    Some(char)
-                },
-                _ = self.delay.delay_ms(CARD_RESET_DRAIN_DELAY.num_milliseconds() as u32).fuse() => {
-                    None
-                }
-            };
+    /// Firmware version of the Notecard, if detected.
+    ///
+    /// Only available once a transaction was executed with `Config::detect_firmware` enabled.
+    pub fn firmware_version(&self) -> Option<FirmwareVersion> {
+        self.firmware_version
+    }
 
-            match read {
-                Some(res) => match res {
-                    Ok(len) => {
-                        trace!(
-                            "nc: Got data count {} buffer {:?}",
-                            len,
-                            core::str::from_utf8(&buffer).unwrap()
-                        )
-                    }
-                    Err(err) => {
-                        error!("nc: Read failed with {}", defmt::Debug2Format(&err))
-                    }
-                },
-                None => {
-                    trace!("nc: Timeout");
-                    break;
-                }
-            }
+    /// Query the firmware version on the first reset and turn pacing off where it allows
+    async fn detect_firmware(&mut self) -> Result<(), error::Error> {
+        if !self.config.detect_firmware || self.firmware_detected {
+            return Ok(());
+        }
 
-            match buffer[0] {
-                b'\r' => carrige_return_found = true,
-                b'\n' => newline_found = true,
-                _ => not_control_char_found = true,
+        let seqno = self.next_sequence_number();
+        let cmd = card::req::CardVersion::default();
+        let encode = |buffer: &mut [u8]| serialize_request(&cmd, buffer);
+        let version = match self.exchange(&encode, &|response| cmd.parse(response), seqno).await {
+            Ok(version) => version.firmware_version(),
+            Err(err) if err.is_transient() => {
+                self.reset_required = true;
+                return Err(err);
+            }
+            Err(err) => {
+                error!("nc: firmware version detection failed with {}", err);
+                None
             }
+        };
 
-            if carrige_return_found && newline_found {
-                if not_control_char_found {
-                    return Ok(ResetResult::NonCRResult);
-                } else {
-                    return Ok(ResetResult::CRResult);
-                }
+        if let Some(version) = version {
+            debug!("nc: detected firmware version {:?}", version);
+            if version >= FAST_PACING_FIRMWARE && self.transport.fast_pacing() {
+                self.apply_fast_pacing();
             }
         }
+        self.firmware_version = version;
+        self.firmware_detected = true;
 
-        Err(error::Error::TimeOut)
+        Ok(())
+    }
+
+    /// Turn pacing off unless its delays or segment length were changed from the defaults
+    fn apply_fast_pacing(&mut self) {
+        let default = Config::default();
+
+        if self.config.chunk_delay == default.chunk_delay
+            && self.config.segment_delay == default.segment_delay
+            && self.config.segment_length == default.segment_length
+        {
+            self.config.pacing = false;
+        }
     }
 
     async fn send_request(&mut self) -> Result<(), error::Error> {
@@ -268,53 +604,351 @@ impl<IFT:Read + Write, D: DelayNs> Notecard<IFT, D> {
 
         trace!("nc: sending request: {:?}", core::str::from_utf8(&self.buffer).ok());
 
-        for segment in self.buffer.chunks(SEGMENT_LENGTH) {
-            self.interface.write_all(segment).await.map_err(|_| error::Error::WriteError)?;
-            self.delay.delay_ms(self.config.segment_delay.num_milliseconds() as u32).await;
-        }
-
-        Ok(())
+        self.transport.send(&mut self.delay, &self.buffer, &self.config).await
     }
 
     async fn read_result(&mut self) -> Result<(), error::Error> {
         // Clear the buffer
         self.buffer.clear();
 
-        // Local variables
-        let mut local_buffer: Vec<u8, 256> = Vec::new();
-        let mut got_newline = false;
-        let mut got_carriage = false;
-        loop {
-            local_buffer.clear();
-            local_buffer.resize(local_buffer.capacity(), 0).ok();
-            let available = self.interface.read(&mut local_buffer.as_mut_slice()).await.map_err(|_| error::Error::ReadError)?;
-            if available == 0 {
-                self.delay.delay_ms(10).await;
-                continue;
-            }
-            local_buffer.truncate(available);
-            trace!("nc: rr: len {} cont {:?}", local_buffer.len(), core::str::from_utf8(&local_buffer).ok());
-            self.buffer.extend(local_buffer.iter().copied());
-            if local_buffer.contains(&b'\n') {
-                got_newline = true;
-            }
-            if local_buffer.contains(&b'\r') {
-                got_carriage = true;
+        let result = self
+            .transport
+            .receive(&mut self.delay, self.buffer.spare_capacity_mut(), self.config.response_timeout)
+            .await;
+
+        match result {
+            Ok(len) => {
+                self.buffer.advance(len);
+                Ok(())
             }
-            if got_newline && got_carriage {
-                debug!("nc: rr: done!");
-                break;
+            Err(error::Error::TimeOut) => {
+                error!("nc: rr: timeout waiting for response");
+                // Whatever arrives late would be taken as the response to the next request.
+                self.reset_required = true;
+                Err(error::Error::TimeOut)
             }
+            Err(err) => Err(err),
         }
+    }
+}
 
-        Ok(())
+/// Serialize a typed request into `buffer`.
+fn serialize_request<T: Serialize>(cmd: &T, buffer: &mut [u8]) -> Result<usize, error::Error> {
+    serde_json_core::to_slice(cmd, buffer).map_err(|_| error::Error::SerError)
+}
+
+/// Turn a serialized request into a command by renaming its leading `"req"` key to `"cmd"`.
+fn request_to_command(request: &mut [u8]) -> Result<(), error::Error> {
+    const REQ: &[u8] = b"{\"req\":";
+    const CMD: &[u8] = b"{\"cmd\":";
+
+    if request.starts_with(REQ) {
+        request[..REQ.len()].copy_from_slice(CMD);
+    } else if !request.starts_with(CMD) {
+        return Err(error::Error::InvalidRequest);
+    }
+    Ok(())
+}
+
+/// Copy a raw JSON request into `buffer`, without its trailing newline.
+fn copy_request(req: &str, buffer: &mut [u8]) -> Result<usize, error::Error> {
+    let req = req.trim_ascii_end().as_bytes();
+    if req.is_empty() || req.contains(&b'\n') {
+        return Err(error::Error::InvalidRequest);
     }
+
+    buffer
+        .get_mut(..req.len())
+        .ok_or(error::Error::SerError)?
+        .copy_from_slice(req);
+    Ok(req.len())
+}
+
+/// Yield once to the executor.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+/// A request sent with [`Notecard::command`], the Notecard does not respond to it.
+///
+/// The first field of the type has to serialize as `req` or `cmd`.
+pub trait NoteCommand: Serialize {}
+
+/// A request whose result borrows from the response, executed with
+/// [`Notecard::transaction_ref`].
+pub trait NoteTransactionRef {
+    type NoteResult<'a>;
+
+    fn parse<'a>(&self, result: &'a [u8]) -> Result<Self::NoteResult<'a>, error::Error>;
 }
 
 pub trait NoteTransaction {
     type NoteResult: DeserializeOwned;
 
     fn parse(&self, result: &[u8]) -> Result<Self::NoteResult, error::Error> {
-        Ok(serde_json_core::from_slice::<Self::NoteResult>(&result).map_err(|_| error::Error::new_desererror(result))?.0)
+        Ok(serde_json_core::from_slice::<Self::NoteResult>(result).map_err(|_| error::Error::new_desererror(result))?.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::block_on;
+    use crate::mock::{MockDelay, MockSerial};
+    use crate::transport::Serial;
+
+    /// Discards the logs of host tests built with `defmt`, there is no probe to send them to.
+    #[cfg(feature = "defmt")]
+    #[defmt::global_logger]
+    struct DiscardLogger;
+
+    #[cfg(feature = "defmt")]
+    unsafe impl defmt::Logger for DiscardLogger {
+        fn acquire() {}
+        unsafe fn flush() {}
+        unsafe fn release() {}
+        unsafe fn write(_bytes: &[u8]) {}
+    }
+
+    #[cfg(feature = "defmt")]
+    defmt::timestamp!("{=u8}", 0);
+
+    fn notecard(mock: &MockSerial, config: Config) -> Notecard<Serial<MockSerial>, MockDelay> {
+        Notecard::new_with_config(Serial::new(mock.clone()), MockDelay::new(), config)
+    }
+
+    #[test]
+    fn transaction() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"mode":"periodic","product":"com.example:test"}"#);
+
+        let mut note = notecard(&mock, Config::default());
+        let hub = block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+
+        assert!(matches!(hub.mode, Some(hub::req::HubMode::Periodic)));
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn transaction_response_in_parts() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond_in_parts(r#"{"product":"com.example:test"}"#, 3);
+
+        let mut note = notecard(&mock, Config::default());
+        let hub = block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn reset_drains_stray_bytes() {
+        let mock = MockSerial::new();
+        mock.expect_request("")
+            .respond_raw(b"{\"late\":true}\r\n")
+            .expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond("{}");
+
+        let mut note = notecard(&mock, Config::default());
+        block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn transaction_timeout() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#);
+
+        let delay = MockDelay::new();
+        let config = Config {
+            transaction_retry: 1,
+            ..Default::default()
+        };
+        let mut note = Notecard::new_with_config(Serial::new(mock.clone()), delay.clone(), config);
+        let result = block_on(note.transaction(hub::req::HubGet::default()));
+
+        assert!(matches!(
+            result,
+            Err(Error::RetriesExhausted { attempts: 2, last: TransientError::TimeOut })
+        ));
+        assert!(delay.elapsed() >= Duration::seconds(10));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn garbled_response_is_resent() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"product":"com.exa"#)
+            .expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"product":"com.example:test"}"#);
+
+        let mut note = notecard(&mock, Config::default());
+        let hub = block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn crc_mismatch_is_resent() {
+        let request = r#"{"req":"hub.get","crc":"0001:E37F7D23"}"#;
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(request)
+            .respond(r#"{"product":"com.example:tesT","crc":"0001:9C8D2413"}"#)
+            .expect_reset()
+            .expect_request(request)
+            .respond(r#"{"product":"com.example:test","crc":"0001:9C8D2413"}"#);
+
+        let config = Config {
+            crc: true,
+            ..Default::default()
+        };
+        let mut note = notecard(&mock, config);
+        let hub = block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn failed_firmware_detection_is_not_repeated() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"card.version"}"#)
+            .respond(r#"{"err":"unknown request {not-supported}"}"#)
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"product":"com.exa"#)
+            .expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"product":"com.example:test"}"#);
+
+        let config = Config {
+            detect_firmware: true,
+            ..Default::default()
+        };
+        let mut note = notecard(&mock, config);
+        let hub = block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        assert_eq!(note.firmware_version(), None);
+        mock.assert_done();
+    }
+
+    #[test]
+    fn mismatched_response_is_not_resent() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"note.add","file":"data.qo","body":1}"#)
+            .respond(r#"{"total":"x"}"#);
+
+        let mut note = notecard(&mock, Config::default());
+        let result = block_on(note.transaction(note::req::NoteAdd::new("data.qo", 1)));
+
+        assert!(matches!(result, Err(Error::DeserError(_))));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn command() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"cmd":"note.add","file":"data.qo","body":1}"#);
+
+        let mut note = notecard(&mock, Config::default());
+        block_on(note.command(note::req::NoteAdd::new("data.qo", 1))).unwrap();
+
+        mock.assert_done();
+    }
+
+    #[test]
+    fn stale_response_is_skipped() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get","id":1}"#)
+            .respond(r#"{"product":"com.example:stale","id":65535}"#)
+            .respond(r#"{"product":"com.example:test","id":1}"#);
+
+        let config = Config {
+            request_id: true,
+            ..Default::default()
+        };
+        let mut note = notecard(&mock, config);
+        let hub = block_on(note.transaction(hub::req::HubGet::default())).unwrap();
+
+        assert_eq!(hub.product.as_deref(), Some("com.example:test"));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn notecard_error() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"err":"no notefile {file-noexist}"}"#);
+
+        let mut note = notecard(&mock, Config::default());
+        let result = block_on(note.transaction(hub::req::HubGet::default()));
+
+        assert!(matches!(result, Err(Error::NotefileNotFound(_))));
+        mock.assert_done();
+    }
+
+    #[test]
+    fn long_notecard_error() {
+        let message = std::format!("{} {{not-supported}}", "x".repeat(300));
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(&std::format!(r#"{{"err":"{}"}}"#, message));
+
+        let mut note = notecard(&mock, Config::default());
+        let result = block_on(note.transaction(hub::req::HubGet::default()));
+        mock.assert_done();
+
+        // The token is past the stored part of the message
+        let Err(Error::NotSupported(err)) = result else {
+            panic!("expected a not-supported error");
+        };
+        assert!(message.starts_with(err.as_str()));
+        assert_eq!(err.len(), err.capacity());
+    }
+
+    #[test]
+    fn parse() {
+        let hub_get = hub::req::HubGet::default();
+
+        let hub = hub_get.parse(br#"{"mode":"continuous","outbound":60,"sync":true}"#).unwrap();
+        assert!(matches!(hub.mode, Some(hub::req::HubMode::Continuous)));
+        assert_eq!(hub.outbound, Some(60));
+        assert_eq!(hub.sync, Some(true));
+
+        let hub = hub_get.parse(b"{}").unwrap();
+        assert!(hub.mode.is_none());
+
+        assert!(matches!(hub_get.parse(br#"{"outbound":"x"}"#), Err(Error::DeserError(_))));
     }
 }
@@ -0,0 +1,239 @@
+//! Scripted Notecard for host unit tests, enabled with the `mock` feature.
+//!
+//! [`MockSerial`] is an `embedded_io` and `embedded_io_async` byte stream that plays a script:
+//! every request line the driver writes must match the next expected request, and responses are
+//! handed out as they come up in the script. Reads stay pending while no response is queued, so a
+//! request without a response times out. [`MockDelay`] completes immediately and adds up the time
+//! it was asked to wait.
+//!
+//! Both are handles on shared state, keep a clone to inspect them once the driver owns the other.
+//!
+//! ```rust, ignore
+//! let mock = MockSerial::new();
+//! mock.expect_reset()
+//!     .expect_request(r#"{"req":"hub.get"}"#)
+//!     .respond(r#"{"mode":"periodic"}"#);
+//!
+//! let mut note = Notecard::new(Serial::new(mock.clone()), MockDelay::new());
+//! let hub = note.transaction(hub::req::HubGet::default()).await?;
+//! mock.assert_done();
+//! ```
+
+use core::cell::{Cell, RefCell};
+use core::convert::Infallible;
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::string::String;
+use std::vec::Vec;
+
+use chrono::Duration;
+use embedded_io::{ErrorType, ReadReady};
+
+enum Step {
+    /// A request line the driver has to write, without the newline.
+    Request(String),
+    /// Data for the driver to read, handed out at most `part` bytes per read.
+    Data { data: Vec<u8>, part: usize },
+}
+
+#[derive(Default)]
+struct State {
+    script: VecDeque<Step>,
+    // Request bytes written since the last newline
+    written: Vec<u8>,
+}
+
+/// Scripted byte stream standing in for the Notecard.
+#[derive(Clone, Default)]
+pub struct MockSerial {
+    state: Rc<RefCell<State>>,
+}
+
+impl MockSerial {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&self, step: Step) -> &Self {
+        self.state.borrow_mut().script.push_back(step);
+        self
+    }
+
+    /// Expect the driver to write `request` followed by a newline.
+    pub fn expect_request(&self, request: &str) -> &Self {
+        self.push(Step::Request(request.into()))
+    }
+
+    /// Expect the reset newline and acknowledge it.
+    pub fn expect_reset(&self) -> &Self {
+        self.expect_request("").respond("")
+    }
+
+    /// Answer with `response` followed by `\r\n` in a single read.
+    pub fn respond(&self, response: &str) -> &Self {
+        self.respond_in_parts(response, usize::MAX)
+    }
+
+    /// Answer with `response` followed by `\r\n`, handing out at most `part` bytes per read.
+    pub fn respond_in_parts(&self, response: &str, part: usize) -> &Self {
+        let mut data: Vec<u8> = response.as_bytes().into();
+        data.extend_from_slice(b"\r\n");
+        self.push(Step::Data {
+            data,
+            part: part.max(1),
+        })
+    }
+
+    /// Hand out `data` as is, e.g. stray bytes or a truncated response.
+    pub fn respond_raw(&self, data: &[u8]) -> &Self {
+        self.push(Step::Data {
+            data: data.into(),
+            part: usize::MAX,
+        })
+    }
+
+    /// Whether the whole script was played.
+    pub fn is_done(&self) -> bool {
+        let state = self.state.borrow();
+        state.script.is_empty() && state.written.is_empty()
+    }
+
+    /// Panic unless the whole script was played.
+    #[track_caller]
+    pub fn assert_done(&self) {
+        let state = self.state.borrow();
+        assert!(
+            state.written.is_empty(),
+            "mock: unterminated request {:?}",
+            String::from_utf8_lossy(&state.written)
+        );
+        if let Some(step) = state.script.front() {
+            match step {
+                Step::Request(request) => panic!("mock: request {request:?} was not sent"),
+                Step::Data { data, .. } => {
+                    panic!("mock: response {:?} was not read", String::from_utf8_lossy(data))
+                }
+            }
+        }
+    }
+
+    fn data_ready(&self) -> bool {
+        matches!(self.state.borrow().script.front(), Some(Step::Data { .. }))
+    }
+
+    fn read_data(&mut self, buf: &mut [u8]) -> usize {
+        let mut state = self.state.borrow_mut();
+        let Some(Step::Data { data, part }) = state.script.front_mut() else {
+            return 0;
+        };
+
+        let len = data.len().min(*part).min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        data.drain(..len);
+        if data.is_empty() {
+            state.script.pop_front();
+        }
+        len
+    }
+
+    #[track_caller]
+    fn write_data(&mut self, buf: &[u8]) -> usize {
+        let mut state = self.state.borrow_mut();
+        for byte in buf {
+            if *byte != b'\n' {
+                state.written.push(*byte);
+                continue;
+            }
+
+            let line = String::from_utf8_lossy(&state.written).into_owned();
+            state.written.clear();
+            match state.script.pop_front() {
+                Some(Step::Request(expected)) => {
+                    assert_eq!(line, expected, "mock: unexpected request")
+                }
+                Some(Step::Data { data, .. }) => panic!(
+                    "mock: got request {line:?} while response {:?} is unread",
+                    String::from_utf8_lossy(&data)
+                ),
+                None => panic!("mock: got request {line:?} after the end of the script"),
+            }
+        }
+        buf.len()
+    }
+}
+
+impl ErrorType for MockSerial {
+    type Error = Infallible;
+}
+
+impl embedded_io_async::Read for MockSerial {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        core::future::poll_fn(|cx| {
+            if self.data_ready() {
+                core::task::Poll::Ready(Ok(self.read_data(buf)))
+            } else {
+                cx.waker().wake_by_ref();
+                core::task::Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl embedded_io_async::Write for MockSerial {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.write_data(buf))
+    }
+}
+
+impl embedded_io::Read for MockSerial {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Ok(self.read_data(buf))
+    }
+}
+
+impl ReadReady for MockSerial {
+    fn read_ready(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.data_ready())
+    }
+}
+
+impl embedded_io::Write for MockSerial {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        Ok(self.write_data(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Delay that completes immediately and keeps track of the time it was asked to wait.
+#[derive(Clone, Default)]
+pub struct MockDelay {
+    elapsed_ns: Rc<Cell<u64>>,
+}
+
+impl MockDelay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Total time waited by this delay and its clones.
+    pub fn elapsed(&self) -> Duration {
+        Duration::nanoseconds(self.elapsed_ns.get() as i64)
+    }
+}
+
+impl embedded_hal_async::delay::DelayNs for MockDelay {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.elapsed_ns.set(self.elapsed_ns.get() + ns as u64);
+    }
+}
+
+impl embedded_hal::delay::DelayNs for MockDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.elapsed_ns.set(self.elapsed_ns.get() + ns as u64);
+    }
+}
@@ -0,0 +1,458 @@
+//! Notes and notefiles.
+//!
+//! Note bodies are typed: requests serialize any `B: Serialize` as the body and responses
+//! deserialize it into `B: DeserializeOwned`, without an intermediate JSON value.
+//!
+//! ```rust, ignore
+//! note.transaction(NoteAdd::new("sensors.qo", &reading)).await?;
+//! let last: Note<Reading> = note.transaction(NoteGet::new("config.db").with_note("last")).await?;
+//! ```
+//!
+//! Received payloads are kept in a string of up to `PAYLOAD_SIZE` bytes, 1 KiB by default. Notes
+//! with larger payloads fail to parse, pick a larger size as in `NoteGet::<Reading, 4096>`.
+
+use serde::{Serialize, Deserialize};
+
+pub use res::Note;
+
+const DEFAULT_PAYLOAD_SIZE: usize = 1024;
+
+pub mod req {
+
+    use core::marker::PhantomData;
+
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+    use crate::{NoteCommand, NoteTransaction, NoteTransactionRef};
+
+    /// Add a note to a notefile, `B` is the type of the note body.
+    ///
+    /// High rate notes can be sent with `Notecard::command`, which does not wait for a response.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction, NoteCommand)]
+    #[note_transaction(result_type = res::Added)]
+    pub struct NoteAdd<'a, B: Serialize> {
+        pub req: &'static str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub file: Option<&'a str>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub note: Option<&'a str>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub body: Option<B>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub payload: Option<&'a str>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sync: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub full: Option<bool>,
+    }
+
+    impl <'a, B: Serialize> NoteAdd<'a, B> {
+        /// Add a note with `body` to `file`.
+        pub fn new(file: &'a str, body: B) -> Self {
+            Self {
+                file: Some(file),
+                body: Some(body),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl <'a, B: Serialize> Default for NoteAdd<'a, B> {
+        fn default() -> Self {
+            Self {
+                req: "note.add",
+                file: Default::default(),
+                note: Default::default(),
+                body: Default::default(),
+                payload: Default::default(),
+                sync: Default::default(),
+                full: Default::default(),
+            }
+        }
+    }
+
+    /// Get a note from a notefile, `B` is the type of the note body.
+    ///
+    /// The payload of the note is received into a string of `PAYLOAD_SIZE` bytes.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Note<B, PAYLOAD_SIZE>)]
+    pub struct NoteGet<'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE> {
+        pub req: &'static str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub file: Option<&'a str>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub note: Option<&'a str>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delete: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub deleted: Option<bool>,
+
+        #[serde(skip)]
+        pub body: PhantomData<B>,
+    }
+
+    impl <'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize> NoteGet<'a, B, PAYLOAD_SIZE> {
+        /// Get the oldest note of `file`.
+        pub fn new(file: &'a str) -> Self {
+            Self {
+                file: Some(file),
+                ..Default::default()
+            }
+        }
+
+        /// Get the note with id `note` instead, for database notefiles.
+        pub fn with_note(self, note: &'a str) -> Self {
+            Self {
+                note: Some(note),
+                ..self
+            }
+        }
+    }
+
+    impl <'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize> Default for NoteGet<'a, B, PAYLOAD_SIZE> {
+        fn default() -> Self {
+            Self {
+                req: "note.get",
+                file: Default::default(),
+                note: Default::default(),
+                delete: Default::default(),
+                deleted: Default::default(),
+                body: Default::default(),
+            }
+        }
+    }
+
+    /// Replace the body and payload of a note in a database notefile.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Empty)]
+    pub struct NoteUpdate<'a, B: Serialize> {
+        pub req: &'static str,
+
+        pub file: &'a str,
+
+        pub note: &'a str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub body: Option<B>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub payload: Option<&'a str>,
+    }
+
+    impl <'a, B: Serialize> NoteUpdate<'a, B> {
+        /// Replace the body of the note with id `note` in `file` with `body`.
+        pub fn new(file: &'a str, note: &'a str, body: B) -> Self {
+            Self {
+                file,
+                note,
+                body: Some(body),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl <'a, B: Serialize> Default for NoteUpdate<'a, B> {
+        fn default() -> Self {
+            Self {
+                req: "note.update",
+                file: Default::default(),
+                note: Default::default(),
+                body: Default::default(),
+                payload: Default::default(),
+            }
+        }
+    }
+
+    /// Delete a note from a database notefile.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Empty)]
+    pub struct NoteDelete<'a> {
+        pub req: &'static str,
+
+        pub file: &'a str,
+
+        pub note: &'a str,
+    }
+
+    impl <'a> NoteDelete<'a> {
+        /// Delete the note with id `note` from `file`.
+        pub fn new(file: &'a str, note: &'a str) -> Self {
+            Self {
+                file,
+                note,
+                ..Default::default()
+            }
+        }
+    }
+
+    impl <'a> Default for NoteDelete<'a> {
+        fn default() -> Self {
+            Self {
+                req: "note.delete",
+                file: Default::default(),
+                note: Default::default(),
+            }
+        }
+    }
+
+    /// Get the notes of a notefile changed since `tracker` last looked, `B` is the type of the
+    /// note bodies.
+    ///
+    /// Executed with `Notecard::transaction_ref`, see [`res::Changes`]. Payloads are received
+    /// into strings of `PAYLOAD_SIZE` bytes.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct NoteChanges<'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE> {
+        pub req: &'static str,
+
+        pub file: &'a str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub tracker: Option<&'a str>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub max: Option<u32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub start: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delete: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub deleted: Option<bool>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub reset: Option<bool>,
+
+        #[serde(skip)]
+        pub body: PhantomData<B>,
+    }
+
+    impl <'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize> NoteChanges<'a, B, PAYLOAD_SIZE> {
+        /// Get the changes of `file` since `tracker` last looked.
+        pub fn new(file: &'a str, tracker: &'a str) -> Self {
+            Self {
+                file,
+                tracker: Some(tracker),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl <'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize> Default for NoteChanges<'a, B, PAYLOAD_SIZE> {
+        fn default() -> Self {
+            Self {
+                req: "note.changes",
+                file: Default::default(),
+                tracker: Default::default(),
+                max: Default::default(),
+                start: Default::default(),
+                delete: Default::default(),
+                deleted: Default::default(),
+                reset: Default::default(),
+                body: Default::default(),
+            }
+        }
+    }
+
+    impl <B: DeserializeOwned, const PAYLOAD_SIZE: usize> NoteTransactionRef for NoteChanges<'_, B, PAYLOAD_SIZE> {
+        type NoteResult<'r> = res::Changes<'r, B, PAYLOAD_SIZE>;
+
+        fn parse<'r>(&self, result: &'r [u8]) -> Result<Self::NoteResult<'r>, crate::Error> {
+            res::Changes::parse(result)
+        }
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[serde(rename_all = "lowercase")]
+    pub enum TemplateFormat {
+        Compact,
+    }
+
+    /// Set the template of a notefile, `B` is the template body with the type hints of the fields.
+    #[derive(Debug, Deserialize, Serialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    #[derive(NoteTransaction)]
+    #[note_transaction(result_type = res::Template)]
+    pub struct NoteTemplate<'a, B: Serialize> {
+        pub req: &'static str,
+
+        pub file: &'a str,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub body: Option<B>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub length: Option<u32>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub port: Option<u8>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub format: Option<TemplateFormat>,
+
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delete: Option<bool>,
+    }
+
+    impl <'a, B: Serialize> NoteTemplate<'a, B> {
+        /// Set the template of `file` to `body`.
+        pub fn new(file: &'a str, body: B) -> Self {
+            Self {
+                file,
+                body: Some(body),
+                ..Default::default()
+            }
+        }
+    }
+
+    impl <'a, B: Serialize> Default for NoteTemplate<'a, B> {
+        fn default() -> Self {
+            Self {
+                req: "note.template",
+                file: Default::default(),
+                body: Default::default(),
+                length: Default::default(),
+                port: Default::default(),
+                format: Default::default(),
+                delete: Default::default(),
+            }
+        }
+    }
+}
+
+pub mod res {
+    use core::marker::PhantomData;
+
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+    use crate::json;
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Empty {}
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Added {
+        /// Number of notes pending in the notefile.
+        pub total: Option<u32>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Note<B, const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE> {
+        /// Id of the note, for database notefiles.
+        pub note: Option<heapless::String<120>>,
+        pub body: Option<B>,
+        /// Base64 encoded binary payload.
+        pub payload: Option<heapless::String<PAYLOAD_SIZE>>,
+        pub time: Option<u32>,
+        pub deleted: Option<bool>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Template {
+        /// Size of a note of the template in bytes.
+        pub bytes: Option<u32>,
+    }
+
+    /// Result of `note.changes`, borrowing the response.
+    ///
+    /// The notes are deserialized one at a time by [`Changes::notes`], so a batch only needs to
+    /// fit in the driver buffer as JSON.
+    pub struct Changes<'a, B, const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE> {
+        /// Number of changed notes, including the ones not returned yet.
+        pub changes: Option<u32>,
+        /// Number of notes in the notefile.
+        pub total: Option<u32>,
+
+        notes: &'a [u8],
+        body: PhantomData<B>,
+    }
+
+    #[derive(Deserialize)]
+    struct ChangesHeader {
+        changes: Option<u32>,
+        total: Option<u32>,
+    }
+
+    impl<'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize> Changes<'a, B, PAYLOAD_SIZE> {
+        pub(crate) fn parse(result: &'a [u8]) -> Result<Self, crate::Error> {
+            let (header, _) = serde_json_core::from_slice::<ChangesHeader>(result)
+                .map_err(|_| crate::Error::new_desererror(result))?;
+
+            let mut notes: &[u8] = b"{}";
+            for member in json::members(result).ok_or_else(|| crate::Error::new_desererror(result))? {
+                let (key, value) = member.map_err(|_| crate::Error::new_desererror(result))?;
+                if key == "notes" {
+                    notes = value;
+                }
+            }
+            if json::members(notes).is_none() {
+                return Err(crate::Error::new_desererror(result));
+            }
+
+            Ok(Self {
+                changes: header.changes,
+                total: header.total,
+                notes,
+                body: PhantomData,
+            })
+        }
+
+        /// The returned notes with their ids.
+        pub fn notes(&self) -> ChangedNotes<'a, B, PAYLOAD_SIZE> {
+            ChangedNotes {
+                members: json::members(self.notes),
+                body: PhantomData,
+            }
+        }
+    }
+
+    /// Iterator over the notes of [`Changes`], deserializing each when it is reached.
+    pub struct ChangedNotes<'a, B, const PAYLOAD_SIZE: usize = DEFAULT_PAYLOAD_SIZE> {
+        members: Option<json::Members<'a>>,
+        body: PhantomData<B>,
+    }
+
+    impl<'a, B: DeserializeOwned, const PAYLOAD_SIZE: usize> Iterator for ChangedNotes<'a, B, PAYLOAD_SIZE> {
+        type Item = Result<(&'a str, Note<B, PAYLOAD_SIZE>), crate::Error>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let member = self.members.as_mut()?.next()?;
+            Some(
+                member
+                    .map_err(|_| crate::Error::new_desererror(b"malformed notes"))
+                    .and_then(|(id, value)| {
+                        serde_json_core::from_slice::<Note<B, PAYLOAD_SIZE>>(value)
+                            .map(|(note, _)| (id, note))
+                            .map_err(|_| crate::Error::new_desererror(value))
+                    }),
+            )
+        }
+    }
+}
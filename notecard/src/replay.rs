@@ -0,0 +1,496 @@
+//! Recording and replaying the traffic of a transport.
+//!
+//! [`Recorder`] wraps any [`NotecardTransport`] and logs every exchange with a timestamp, one
+//! per line:
+//!
+//! ```text
+//! 1200 ~ ok
+//! 1250 > {"req":"hub.get"}
+//! 1390 < {"mode":"periodic","host":"a.notefile.net"}
+//! 6400 < !timeout
+//! ```
+//!
+//! - `>`: a request sent to the Notecard, without the newline.
+//! - `<`: a response line received, without the `\r\n`.
+//! - `~`: the outcome of a resync, `ok` on success.
+//!
+//! Bytes that are not UTF-8, line breaks and a leading `!` are logged as `\xNN`, a backslash only
+//! where it is followed by `x`. Replaying turns them back into the raw bytes, so a garbled
+//! response fails the same way it did when it was recorded.
+//!
+//! Failures are logged as `!` followed by `write`, `read`, `timeout`, `overflow` or `remaining`.
+//! Empty lines and lines starting with `#` are ignored when replaying, so a capture can be
+//! annotated.
+//!
+//! [`Replay`] plays such a log back in place of the Notecard. It waits for the time between the
+//! recorded entries, so timing dependent behavior is reproduced along with the responses, and
+//! fails with `Error::ReplayMismatch` when the driver sends a different request than the log.
+//!
+//! ```rust, ignore
+//! let mut note = Notecard::new(Replay::new(include_str!("field-unit.log")), delay);
+//! ```
+
+use core::fmt::Write;
+use core::iter::Peekable;
+use core::str::Lines;
+
+use chrono::Duration;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io::ErrorKind;
+
+use crate::error::Error;
+use crate::transport::NotecardTransport;
+use crate::Config;
+
+const REQUEST: &str = ">";
+const RESPONSE: &str = "<";
+const RESYNC: &str = "~";
+const RESYNC_OK: &str = "ok";
+
+/// Log token of a transport error.
+fn error_token(err: &Error) -> &'static str {
+    match err {
+        Error::WriteError(_) => "write",
+        Error::TimeOut => "timeout",
+        Error::BufOverflow => "overflow",
+        Error::RemainingData => "remaining",
+        _ => "read",
+    }
+}
+
+/// Write `data` to `sink`, escaping what would not survive a log line as `\xNN`.
+fn write_escaped<W: Write>(sink: &mut W, data: &[u8]) -> core::fmt::Result {
+    let mut first = true;
+    for chunk in data.utf8_chunks() {
+        let mut chars = chunk.valid().chars().peekable();
+        while let Some(c) = chars.next() {
+            let escape =
+                matches!(c, '\r' | '\n') || (first && c == '!') || (c == '\\' && chars.peek() == Some(&'x'));
+            if escape {
+                write!(sink, "\\x{:02X}", c as u32)?;
+            } else {
+                sink.write_char(c)?;
+            }
+            first = false;
+        }
+        for byte in chunk.invalid() {
+            write!(sink, "\\x{:02X}", byte)?;
+            first = false;
+        }
+    }
+    Ok(())
+}
+
+/// The raw bytes of data written by [`write_escaped`].
+fn unescaped(data: &str) -> impl Iterator<Item = u8> + '_ {
+    let mut rest = data.as_bytes();
+    core::iter::from_fn(move || {
+        let (&byte, tail) = rest.split_first()?;
+        let escaped = rest
+            .strip_prefix(b"\\x")
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| core::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                rest = &rest[4..];
+                Some(byte)
+            }
+            None => {
+                rest = tail;
+                Some(byte)
+            }
+        }
+    })
+}
+
+/// Transport error of a log token.
+fn token_error(token: &str) -> Error {
+    match token {
+        "write" => Error::WriteError(ErrorKind::Other),
+        "timeout" => Error::TimeOut,
+        "overflow" => Error::BufOverflow,
+        "remaining" => Error::RemainingData,
+        _ => Error::ReadError(ErrorKind::Other),
+    }
+}
+
+/// Transport logging the traffic of another transport to `sink`.
+///
+/// `clock` returns the current time in milliseconds. Failing to write to the sink does not fail
+/// the exchange.
+pub struct Recorder<T: NotecardTransport, W: Write, C: FnMut() -> u64> {
+    inner: T,
+    sink: W,
+    clock: C,
+}
+
+impl<T: NotecardTransport, W: Write, C: FnMut() -> u64> Recorder<T, W, C> {
+    pub fn new(inner: T, sink: W, clock: C) -> Self {
+        Self { inner, sink, clock }
+    }
+
+    /// Release the wrapped transport and the sink.
+    pub fn release(self) -> (T, W) {
+        (self.inner, self.sink)
+    }
+
+    fn log(&mut self, direction: &str, data: &[u8]) {
+        let now = (self.clock)();
+        write!(self.sink, "{} {} ", now, direction)
+            .and_then(|()| write_escaped(&mut self.sink, data))
+            .and_then(|()| self.sink.write_char('\n'))
+            .ok();
+    }
+
+    fn log_error(&mut self, direction: &str, err: &Error) {
+        let now = (self.clock)();
+        writeln!(self.sink, "{} {} !{}", now, direction, error_token(err)).ok();
+    }
+}
+
+impl<T: NotecardTransport, W: Write, C: FnMut() -> u64> NotecardTransport for Recorder<T, W, C> {
+    async fn send<D: DelayNs>(&mut self, delay: &mut D, request: &[u8], config: &Config) -> Result<(), Error> {
+        self.log(REQUEST, request.strip_suffix(b"\n").unwrap_or(request));
+        let result = self.inner.send(delay, request, config).await;
+        if let Err(err) = &result {
+            self.log_error(REQUEST, err);
+        }
+        result
+    }
+
+    async fn receive<D: DelayNs>(&mut self, delay: &mut D, line: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let result = self.inner.receive(delay, line, timeout).await;
+        match &result {
+            Ok(len) => {
+                let response = line[..*len].trim_ascii_end();
+                self.log(RESPONSE, response);
+            }
+            Err(err) => self.log_error(RESPONSE, err),
+        }
+        result
+    }
+
+    async fn resync<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), Error> {
+        let result = self.inner.resync(delay).await;
+        match &result {
+            Ok(()) => self.log(RESYNC, RESYNC_OK.as_bytes()),
+            Err(err) => self.log_error(RESYNC, err),
+        }
+        result
+    }
+
+    fn fast_pacing(&self) -> bool {
+        self.inner.fast_pacing()
+    }
+}
+
+/// One parsed log line.
+struct Entry<'a> {
+    time: u64,
+    direction: &'a str,
+    data: &'a str,
+}
+
+impl<'a> Entry<'a> {
+    fn parse(line: &'a str) -> Option<Self> {
+        let (time, rest) = line.split_once(' ')?;
+        let (direction, data) = rest.split_once(' ').unwrap_or((rest, ""));
+        Some(Self {
+            time: time.parse().ok()?,
+            direction,
+            data,
+        })
+    }
+
+    /// The recorded failure, if this entry is one.
+    fn error(&self) -> Option<Error> {
+        self.data.strip_prefix('!').map(token_error)
+    }
+}
+
+/// Transport playing back a log written by [`Recorder`].
+///
+/// Requests are taken from the log in order, one differing from the request sent fails with
+/// `Error::ReplayMismatch`. Once the log has no matching entry left the Notecard stays silent and
+/// receives time out.
+pub struct Replay<'a> {
+    lines: Peekable<Lines<'a>>,
+    last_time: Option<u64>,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(log: &'a str) -> Self {
+        Self {
+            lines: log.lines().peekable(),
+            last_time: None,
+        }
+    }
+
+    /// Whether the whole log was played.
+    pub fn is_done(&mut self) -> bool {
+        self.skip_ignored();
+        self.lines.peek().is_none()
+    }
+
+    fn skip_ignored(&mut self) {
+        while let Some(line) = self.lines.peek() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') && Entry::parse(line).is_some() {
+                break;
+            }
+            self.lines.next();
+        }
+    }
+
+    fn peek(&mut self) -> Option<Entry<'a>> {
+        self.skip_ignored();
+        Entry::parse(self.lines.peek()?.trim())
+    }
+
+    /// Take the next entry if it goes in `direction`, after waiting for its recorded time.
+    async fn next<D: DelayNs>(&mut self, delay: &mut D, direction: &str) -> Option<Entry<'a>> {
+        let entry = self.peek()?;
+        if entry.direction != direction {
+            return None;
+        }
+        self.lines.next();
+
+        if let Some(last_time) = self.last_time {
+            let gap = entry.time.saturating_sub(last_time);
+            if gap > 0 {
+                delay.delay_ms(gap.min(u32::MAX as u64) as u32).await;
+            }
+        }
+        self.last_time = Some(entry.time);
+
+        Some(entry)
+    }
+}
+
+impl NotecardTransport for Replay<'_> {
+    async fn send<D: DelayNs>(&mut self, delay: &mut D, request: &[u8], _config: &Config) -> Result<(), Error> {
+        let Some(entry) = self.next(delay, REQUEST).await else {
+            return Ok(());
+        };
+        let request = request.strip_suffix(b"\n").unwrap_or(request);
+        if !unescaped(entry.data).eq(request.iter().copied()) {
+            return Err(Error::new_replay_mismatch(request));
+        }
+
+        // A failed send is logged right after the request
+        let failed = self.peek().is_some_and(|entry| entry.direction == REQUEST && entry.error().is_some());
+        if !failed {
+            return Ok(());
+        }
+        match self.next(delay, REQUEST).await.and_then(|entry| entry.error()) {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    async fn receive<D: DelayNs>(&mut self, delay: &mut D, line: &mut [u8], _timeout: Duration) -> Result<usize, Error> {
+        let entry = self.next(delay, RESPONSE).await.ok_or(Error::TimeOut)?;
+        if let Some(err) = entry.error() {
+            return Err(err);
+        }
+
+        let mut len = 0;
+        for byte in unescaped(entry.data).chain(*b"\r\n") {
+            *line.get_mut(len).ok_or(Error::BufOverflow)? = byte;
+            len += 1;
+        }
+        Ok(len)
+    }
+
+    async fn resync<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), Error> {
+        let entry = self.next(delay, RESYNC).await.ok_or(Error::TimeOut)?;
+        match entry.error() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+
+    use super::*;
+    use crate::mock::{MockDelay, MockSerial};
+    use crate::blocking::block_on;
+    use crate::transport::Serial;
+    use crate::{hub, Notecard};
+
+    fn hub_get<T: NotecardTransport>(note: &mut Notecard<T, MockDelay>) -> Result<hub::res::Hub, Error> {
+        block_on(note.transaction(hub::req::HubGet::default()))
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mock = MockSerial::new();
+        mock.expect_request("")
+            .respond_raw(b"x\r\n")
+            .expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"host":"a.notefile.net"}"#);
+
+        let delay = MockDelay::new();
+        let clock = delay.clone();
+        let recorder = Recorder::new(Serial::new(mock.clone()), String::new(), move || {
+            clock.elapsed().num_milliseconds() as u64
+        });
+        let mut note = Notecard::new(recorder, delay.clone());
+        assert_eq!(hub_get(&mut note).unwrap().host.as_deref(), Some("a.notefile.net"));
+        mock.assert_done();
+
+        let (recorder, _) = note.suspend();
+        let (_, log) = recorder.release();
+        let lines: std::vec::Vec<_> = log.lines().map(|line| line.split_once(' ').unwrap().1).collect();
+        assert_eq!(
+            lines,
+            [
+                "~ !remaining",
+                "~ ok",
+                r#"> {"req":"hub.get"}"#,
+                "< !timeout",
+                "~ ok",
+                r#"> {"req":"hub.get"}"#,
+                r#"< {"host":"a.notefile.net"}"#,
+            ]
+        );
+
+        // Playing back waits for the recorded gaps, from the first entry on
+        let first = Entry::parse(log.lines().next().unwrap()).unwrap().time;
+        let last = Entry::parse(log.lines().last().unwrap()).unwrap().time;
+
+        let replay_delay = MockDelay::new();
+        let mut note = Notecard::new(Replay::new(&log), replay_delay.clone());
+        assert_eq!(hub_get(&mut note).unwrap().host.as_deref(), Some("a.notefile.net"));
+        assert_eq!(replay_delay.elapsed().num_milliseconds() as u64, last - first);
+
+        let (mut replay, _) = note.suspend();
+        assert!(replay.is_done());
+    }
+
+    #[test]
+    fn escaping() {
+        let data = b"!{\"err\":\"\\x41 \\\"\xFF\r\n\"}";
+        let mut log = String::new();
+        write_escaped(&mut log, data).unwrap();
+
+        assert_eq!(log, r#"\x21{"err":"\x5Cx41 \"\xFF\x0D\x0A"}"#);
+        assert!(unescaped(&log).eq(data.iter().copied()));
+        assert!(unescaped(r"\x4 \xZZ \").eq(r"\x4 \xZZ \".bytes()));
+    }
+
+    #[test]
+    fn record_and_replay_invalid_response() {
+        let mock = MockSerial::new();
+        mock.expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond_raw(b"{\"host\":\"a.notefile.net\"\xFE\xFF}\r\n")
+            .expect_reset()
+            .expect_request(r#"{"req":"hub.get"}"#)
+            .respond(r#"{"host":"a.notefile.net"}"#);
+
+        let recorder = Recorder::new(Serial::new(mock.clone()), String::new(), || 0);
+        let mut note = Notecard::new(recorder, MockDelay::new());
+        assert_eq!(hub_get(&mut note).unwrap().host.as_deref(), Some("a.notefile.net"));
+        mock.assert_done();
+
+        let (recorder, _) = note.suspend();
+        let (_, log) = recorder.release();
+        assert!(log.contains(r#"0 < {"host":"a.notefile.net"\xFE\xFF}"#));
+
+        // The garbled response is played back byte for byte, so the driver resends as it did
+        let mut replay = Replay::new(&log);
+        let mut delay = MockDelay::new();
+        block_on(replay.resync(&mut delay)).unwrap();
+        block_on(replay.send(&mut delay, b"{\"req\":\"hub.get\"}\n", &Config::default())).unwrap();
+        let mut line = [0_u8; 64];
+        let len = block_on(replay.receive(&mut delay, &mut line, Duration::seconds(5))).unwrap();
+        assert_eq!(&line[..len], b"{\"host\":\"a.notefile.net\"\xFE\xFF}\r\n");
+
+        let mut note = Notecard::new(Replay::new(&log), MockDelay::new());
+        assert_eq!(hub_get(&mut note).unwrap().host.as_deref(), Some("a.notefile.net"));
+        let (mut replay, _) = note.suspend();
+        assert!(replay.is_done());
+    }
+
+    #[test]
+    fn replay_request_mismatch() {
+        let log = "0 ~ ok\n\
+                   10 > {\"req\":\"card.version\"}\n\
+                   20 < {\"version\":\"notecard-8.1.3\"}\n";
+        let mut note = Notecard::new(Replay::new(log), MockDelay::new());
+
+        match hub_get(&mut note) {
+            Err(Error::ReplayMismatch(request)) => assert_eq!(request, r#"{"req":"hub.get"}"#),
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("mismatched request replayed"),
+        }
+    }
+
+    #[test]
+    fn error_tokens() {
+        let errors = [
+            Error::WriteError(ErrorKind::Other),
+            Error::ReadError(ErrorKind::Other),
+            Error::TimeOut,
+            Error::BufOverflow,
+            Error::RemainingData,
+        ];
+        for err in errors {
+            let replayed = token_error(error_token(&err));
+            assert_eq!(std::format!("{:?}", replayed), std::format!("{:?}", err));
+        }
+    }
+
+    #[test]
+    fn replay_errors() {
+        let log = "0 ~ ok\n\
+                   10 > {\"req\":\"hub.get\"}\n\
+                   10 > !write\n\
+                   20 ~ ok\n\
+                   30 > {\"req\":\"hub.get\"}\n\
+                   5030 < !timeout\n";
+        let mut replay = Replay::new(log);
+        let mut delay = MockDelay::new();
+        let config = Config::default();
+
+        assert!(block_on(replay.resync(&mut delay)).is_ok());
+        let sent = block_on(replay.send(&mut delay, b"{\"req\":\"hub.get\"}\n", &config));
+        assert!(matches!(sent, Err(Error::WriteError(_))));
+        assert!(block_on(replay.resync(&mut delay)).is_ok());
+        assert!(block_on(replay.send(&mut delay, b"{\"req\":\"hub.get\"}\n", &config)).is_ok());
+
+        let mut line = [0_u8; 64];
+        let received = block_on(replay.receive(&mut delay, &mut line, Duration::seconds(5)));
+        assert!(matches!(received, Err(Error::TimeOut)));
+        assert_eq!(delay.elapsed(), Duration::milliseconds(5030));
+        assert!(replay.is_done());
+    }
+
+    #[test]
+    fn replay_skips_comments_and_blank_lines() {
+        let log = "# field unit 7, firmware 8.1.3\n\
+                   \n\
+                   0 ~ ok\n\
+                   # resent after a timeout\n\
+                   \x20\x20\n\
+                   10 > {\"req\":\"hub.get\"}\n\
+                   \n\
+                   40 < {\"host\":\"a.notefile.net\"}\n\
+                   # end of capture\n";
+        let replay_delay = MockDelay::new();
+        let mut note = Notecard::new(Replay::new(log), replay_delay.clone());
+        assert_eq!(hub_get(&mut note).unwrap().host.as_deref(), Some("a.notefile.net"));
+        assert_eq!(replay_delay.elapsed(), Duration::milliseconds(40));
+
+        let (mut replay, _) = note.suspend();
+        assert!(replay.is_done());
+    }
+}
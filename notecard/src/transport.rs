@@ -0,0 +1,418 @@
+//! Links between the driver and the Notecard.
+//!
+//! A [`NotecardTransport`] moves newline terminated JSON requests to the Notecard and `\r\n`
+//! terminated responses back. Transaction logic, retries and parsing live in [`crate::Notecard`]
+//! on top of it.
+//!
+//! - [`Serial`]: the primary UART (9600 baud) and the AUX UART (115200 baud), or any other
+//!   `embedded_io_async` byte stream.
+//! - [`crate::i2c::I2cSerial`]: the Notecard serial-over-I2C protocol.
+//!
+//! Custom links, e.g. an RS-485 bridge, implement the trait and can reuse [`paced_chunks`] to
+//! honor the pacing configuration.
+
+use chrono::Duration;
+use embedded_hal_async::delay::DelayNs;
+use embedded_io::Error as _;
+use embedded_io_async::{Read, Write};
+use futures::{select_biased, FutureExt};
+
+use crate::error::Error;
+use crate::fmt::Debug2Format;
+use crate::{Config, CARD_RESET_DRAIN_DELAY, CHUNK_LENGTH};
+
+/// Delay before reading again when the interface returned no data (ms).
+const IDLE_DELAY_MS: u32 = 10;
+
+#[allow(async_fn_in_trait)]
+pub trait NotecardTransport {
+    /// Send a complete newline terminated request, pacing it according to `config`.
+    async fn send<D: DelayNs>(&mut self, delay: &mut D, request: &[u8], config: &Config) -> Result<(), Error>;
+
+    /// Receive one `\r\n` terminated response line into `line` and return its length.
+    ///
+    /// Fails with `Error::TimeOut` when the line is not complete after `timeout`. A line longer
+    /// than `line` is read to its end and discarded with `Error::BufOverflow`.
+    async fn receive<D: DelayNs>(&mut self, delay: &mut D, line: &mut [u8], timeout: Duration) -> Result<usize, Error>;
+
+    /// Bring the link back in sync with the Notecard.
+    ///
+    /// Succeeds when the Notecard acknowledged a newline with a bare `\r\n`. Fails with
+    /// `Error::RemainingData` when stray data was drained and with `Error::TimeOut` when the
+    /// Notecard did not answer. Both are worth another attempt.
+    async fn resync<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), Error>;
+
+    /// Whether the link keeps up with unpaced requests on firmware 3.4 and above, see
+    /// `Config::detect_firmware`.
+    ///
+    /// The primary UART and i2c need the full pacing, only the AUX UART opts in.
+    fn fast_pacing(&self) -> bool {
+        false
+    }
+}
+
+/// Split `request` into chunks according to the pacing configuration.
+///
+/// Yields the delay to wait before sending each chunk (ms), the chunk delay between the chunks of
+/// a segment and the segment delay between segments. Without pacing the request is a single chunk.
+pub fn paced_chunks<'a>(request: &'a [u8], config: &Config) -> PacedChunks<'a> {
+    if config.pacing {
+        PacedChunks {
+            remaining: request,
+            chunk_length: CHUNK_LENGTH,
+            segment_length: config.segment_length.max(1),
+            segment_left: config.segment_length.max(1),
+            chunk_delay: config.chunk_delay.num_milliseconds() as u32,
+            segment_delay: config.segment_delay.num_milliseconds() as u32,
+            first: true,
+        }
+    } else {
+        PacedChunks {
+            remaining: request,
+            chunk_length: usize::MAX,
+            segment_length: usize::MAX,
+            segment_left: usize::MAX,
+            chunk_delay: 0,
+            segment_delay: 0,
+            first: true,
+        }
+    }
+}
+
+/// Iterator returned by [`paced_chunks`].
+pub struct PacedChunks<'a> {
+    remaining: &'a [u8],
+    chunk_length: usize,
+    segment_length: usize,
+    segment_left: usize,
+    chunk_delay: u32,
+    segment_delay: u32,
+    first: bool,
+}
+
+impl<'a> Iterator for PacedChunks<'a> {
+    type Item = (u32, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining.is_empty() {
+            return None;
+        }
+
+        let delay = if self.first {
+            0
+        } else if self.segment_left == 0 {
+            self.segment_left = self.segment_length;
+            self.segment_delay
+        } else {
+            self.chunk_delay
+        };
+        self.first = false;
+
+        let len = self.remaining.len().min(self.chunk_length).min(self.segment_left);
+        let (chunk, remaining) = self.remaining.split_at(len);
+        self.remaining = remaining;
+        self.segment_left -= len;
+
+        Some((delay, chunk))
+    }
+}
+
+/// Accumulates received data into a response line.
+pub(crate) struct LineReader<'a> {
+    line: &'a mut [u8],
+    len: usize,
+    got_newline: bool,
+    got_carriage: bool,
+    overflow: bool,
+}
+
+impl<'a> LineReader<'a> {
+    pub(crate) fn new(line: &'a mut [u8]) -> Self {
+        Self {
+            line,
+            len: 0,
+            got_newline: false,
+            got_carriage: false,
+            overflow: false,
+        }
+    }
+
+    /// Append received data, returns the result once the line is complete.
+    pub(crate) fn push(&mut self, data: &[u8]) -> Option<Result<usize, Error>> {
+        trace!("nc: rr: len {} cont {:?}", data.len(), core::str::from_utf8(data).ok());
+
+        let end = self.len + data.len();
+        if !self.overflow {
+            if end > self.line.len() {
+                // Keep reading until the end of the line so the next response starts in sync
+                error!("nc: rr: response exceeds buffer of {} bytes, draining", self.line.len());
+                self.overflow = true;
+            } else {
+                self.line[self.len..end].copy_from_slice(data);
+                self.len = end;
+            }
+        }
+        if data.contains(&b'\n') {
+            self.got_newline = true;
+        }
+        if data.contains(&b'\r') {
+            self.got_carriage = true;
+        }
+
+        if self.got_newline && self.got_carriage {
+            if self.overflow {
+                return Some(Err(Error::BufOverflow));
+            }
+            debug!("nc: rr: done!");
+            return Some(Ok(self.len));
+        }
+
+        None
+    }
+}
+
+/// Classifies the bytes received in answer to a reset newline.
+#[derive(Default)]
+pub(crate) struct ResetScanner {
+    carrige_return_found: bool,
+    newline_found: bool,
+    not_control_char_found: bool,
+}
+
+impl ResetScanner {
+    /// Feed a received byte, returns the resync result once a `\r\n` was seen.
+    pub(crate) fn push(&mut self, byte: u8) -> Option<Result<(), Error>> {
+        match byte {
+            b'\r' => self.carrige_return_found = true,
+            b'\n' => self.newline_found = true,
+            _ => self.not_control_char_found = true,
+        }
+
+        if self.carrige_return_found && self.newline_found {
+            if self.not_control_char_found {
+                debug!("Found unexpected return characters. Retrying sync.");
+                return Some(Err(Error::RemainingData));
+            } else {
+                return Some(Ok(()));
+            }
+        }
+
+        None
+    }
+}
+
+/// Transport for the Notecard UART and AUX UART.
+///
+/// Works with any `embedded_io_async` byte stream, e.g. a buffered UART.
+pub struct Serial<IFT: Read + Write> {
+    interface: IFT,
+    aux: bool,
+}
+
+impl<IFT: Read + Write> Serial<IFT> {
+    pub fn new(interface: IFT) -> Self {
+        Self { interface, aux: false }
+    }
+
+    /// Transport for the AUX UART, which turns pacing off once firmware detection found version
+    /// 3.4 or above.
+    pub fn new_aux(interface: IFT) -> Self {
+        Self { interface, aux: true }
+    }
+
+    /// Release the interface.
+    pub fn release(self) -> IFT {
+        self.interface
+    }
+
+    /// Read from the interface until a `\r\n` terminated line was received.
+    ///
+    /// Returns `None` when the interface had no data, the caller waits before reading again.
+    async fn read_line(interface: &mut IFT, reader: &mut LineReader<'_>) -> Result<Option<usize>, Error> {
+        let mut local_buffer = [0_u8; 256];
+        loop {
+            let available = interface.read(&mut local_buffer).await.map_err(|err| Error::ReadError(err.kind()))?;
+            if available == 0 {
+                return Ok(None);
+            }
+            if let Some(result) = reader.push(&local_buffer[..available]) {
+                return result.map(Some);
+            }
+        }
+    }
+}
+
+impl<IFT: Read + Write> NotecardTransport for Serial<IFT> {
+    async fn send<D: DelayNs>(&mut self, delay: &mut D, request: &[u8], config: &Config) -> Result<(), Error> {
+        for (pause, chunk) in paced_chunks(request, config) {
+            if pause > 0 {
+                delay.delay_ms(pause).await;
+            }
+            self.interface.write_all(chunk).await.map_err(|err| Error::WriteError(err.kind()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn receive<D: DelayNs>(&mut self, delay: &mut D, line: &mut [u8], timeout: Duration) -> Result<usize, Error> {
+        let mut reader = LineReader::new(line);
+        let mut budget_ms = timeout.num_milliseconds() as u32;
+        loop {
+            // Race the response against the rest of the timeout
+            let read = select_biased! {
+                res = Self::read_line(&mut self.interface, &mut reader).fuse() => res,
+                _ = delay.delay_ms(budget_ms).fuse() => Err(Error::TimeOut),
+            };
+
+            if let Some(len) = read? {
+                return Ok(len);
+            }
+
+            // Nothing to read, back off instead of spinning the executor
+            if budget_ms == 0 {
+                return Err(Error::TimeOut);
+            }
+            let idle = IDLE_DELAY_MS.min(budget_ms);
+            delay.delay_ms(idle).await;
+            budget_ms -= idle;
+        }
+    }
+
+    fn fast_pacing(&self) -> bool {
+        self.aux
+    }
+
+    async fn resync<D: DelayNs>(&mut self, delay: &mut D) -> Result<(), Error> {
+        if let Err(e) = self.interface.write_all(b"\n").await {
+            error!(
+                "nc: Sending reset newline failed with {:?}",
+                Debug2Format(&e)
+            );
+            delay
+                .delay_ms(CARD_RESET_DRAIN_DELAY.num_milliseconds() as u32)
+                .await;
+        }
+
+        let mut scanner = ResetScanner::default();
+        loop {
+            let mut buffer = [0_u8; 1];
+            let read = select_biased! {
+                char = self.interface.read(&mut buffer).fuse() => {
+                    Some(char)
+                },
+                _ = delay.delay_ms(CARD_RESET_DRAIN_DELAY.num_milliseconds() as u32).fuse() => {
+                    None
+                }
+            };
+
+            match read {
+                Some(Ok(len)) => {
+                    trace!(
+                        "nc: Got data count {} buffer {:?}",
+                        len,
+                        core::str::from_utf8(&buffer).ok()
+                    );
+                    if len == 0 {
+                        delay.delay_ms(IDLE_DELAY_MS).await;
+                        continue;
+                    }
+                }
+                Some(Err(err)) => {
+                    error!("nc: Read failed with {:?}", Debug2Format(&err));
+                    return Err(Error::ReadError(err.kind()));
+                }
+                None => {
+                    trace!("nc: Timeout");
+                    return Err(Error::TimeOut);
+                }
+            }
+
+            if let Some(result) = scanner.push(buffer[0]) {
+                return result;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockDelay, MockSerial};
+    use crate::blocking::block_on;
+
+    #[test]
+    fn pacing() {
+        let config = Config {
+            segment_length: 2 * CHUNK_LENGTH,
+            ..Default::default()
+        };
+        let request = [b'x'; 3 * CHUNK_LENGTH + 1];
+
+        let chunks: std::vec::Vec<_> = paced_chunks(&request, &config).map(|(delay, chunk)| (delay, chunk.len())).collect();
+        assert_eq!(
+            chunks,
+            [(0, CHUNK_LENGTH), (20, CHUNK_LENGTH), (250, CHUNK_LENGTH), (20, 1)]
+        );
+
+        let config = Config {
+            pacing: false,
+            ..config
+        };
+        assert_eq!(paced_chunks(&request, &config).count(), 1);
+    }
+
+    #[test]
+    fn receive_in_parts() {
+        let mock = MockSerial::new();
+        mock.respond_in_parts(r#"{"total":1}"#, 2);
+
+        let mut serial = Serial::new(mock.clone());
+        let mut line = [0_u8; 32];
+        let len = block_on(serial.receive(&mut MockDelay::new(), &mut line, Duration::seconds(1))).unwrap();
+
+        assert_eq!(&line[..len], b"{\"total\":1}\r\n");
+        mock.assert_done();
+    }
+
+    #[test]
+    fn receive_overflow() {
+        let mock = MockSerial::new();
+        mock.respond(r#"{"total":1}"#).respond("{}");
+
+        let mut serial = Serial::new(mock.clone());
+        let mut line = [0_u8; 8];
+        let mut delay = MockDelay::new();
+        let result = block_on(serial.receive(&mut delay, &mut line, Duration::seconds(1)));
+        assert!(matches!(result, Err(Error::BufOverflow)));
+
+        // The rest of the long line was drained, the next one is read in sync
+        let len = block_on(serial.receive(&mut delay, &mut line, Duration::seconds(1))).unwrap();
+        assert_eq!(&line[..len], b"{}\r\n");
+        mock.assert_done();
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let mock = MockSerial::new();
+        let mut delay = MockDelay::new();
+
+        let mut serial = Serial::new(mock.clone());
+        let mut line = [0_u8; 8];
+        let result = block_on(serial.receive(&mut delay, &mut line, Duration::seconds(2)));
+
+        assert!(matches!(result, Err(Error::TimeOut)));
+        assert_eq!(delay.elapsed(), Duration::seconds(2));
+    }
+
+    #[test]
+    fn resync_stray_bytes() {
+        let mock = MockSerial::new();
+        mock.expect_request("").respond_raw(b"x\r\n").expect_reset();
+
+        let mut serial = Serial::new(mock.clone());
+        let mut delay = MockDelay::new();
+        assert!(matches!(block_on(serial.resync(&mut delay)), Err(Error::RemainingData)));
+        assert!(block_on(serial.resync(&mut delay)).is_ok());
+        mock.assert_done();
+    }
+}